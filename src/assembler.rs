@@ -0,0 +1,469 @@
+use crate::instruction::Instruction;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+//programs are assembled to run from the usual load address
+const ORIGIN: u16 = 0x200;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+//where each source line and label ended up, so debuggers can work in terms of the source
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    //the file the lines refer to, when it is known
+    source: Option<String>,
+    //line number of every instruction to its address
+    lines: BTreeMap<usize, u16>,
+    labels: BTreeMap<u16, String>
+}
+
+impl SourceMap {
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn set_source(&mut self, path: String) {
+        self.source = Some(path);
+    }
+
+    pub fn address_of_line(&self, line: usize) -> Option<u16> {
+        self.lines.get(&line).copied()
+    }
+
+    pub fn line_of_address(&self, address: u16) -> Option<usize> {
+        self.lines.iter().find(|(_, line_address)| **line_address == address).map(|(line, _)| *line)
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|label| label.as_str())
+    }
+
+    //the closest label at or before an address, naming the routine it is in
+    pub fn label_before(&self, address: u16) -> Option<(&str, u16)> {
+        self.labels.range(..=address).next_back().map(|(address, label)| (label.as_str(), *address))
+    }
+
+    //reads the text written by Display, one "source PATH", "line N 0xADDR" or "label NAME 0xADDR" per line
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut map = SourceMap::default();
+        for (number, line) in text.lines().enumerate() {
+            //paths may contain spaces or semicolons, so they take the rest of the line
+            if let Some(path) = line.strip_prefix("source ") {
+                map.source = Some(path.to_string());
+                continue;
+            }
+            let words: Vec<&str> = line.split(';').next().unwrap_or("").split_whitespace().collect();
+            let address = |text: &str| parse_number(text).filter(|address| *address <= 0xFFFF).map(|address| address as u16);
+            match words.as_slice() {
+                [] => {}
+                ["line", line, value] => {
+                    let line = line.parse().ok();
+                    match (line, address(value)) {
+                        (Some(line), Some(value)) => {map.lines.insert(line, value);}
+                        _ => return Err(format!("line {}: invalid line entry", number + 1))
+                    }
+                }
+                ["label", name, value] => match address(value) {
+                    Some(value) => {map.labels.insert(value, name.to_string());}
+                    None => return Err(format!("line {}: invalid label entry", number + 1))
+                },
+                _ => return Err(format!("line {}: expected a line or label entry", number + 1))
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(source) = &self.source {
+            writeln!(f, "source {}", source)?;
+        }
+        for (line, address) in self.lines.iter() {
+            writeln!(f, "line {} 0x{:03X}", line, address)?;
+        }
+        for (address, label) in self.labels.iter() {
+            writeln!(f, "label {} 0x{:03X}", label, address)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(String),
+    //a number, label or constant, resolved in the second pass
+    Value(String)
+}
+
+enum Statement {
+    Instruction{mnemonic: String, operands: Vec<Operand>},
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Sprite(Vec<u8>)
+}
+
+impl Statement {
+    fn length(&self) -> u16 {
+        match self {
+            Statement::Instruction{operands, ..} => {
+                if operands.iter().any(|operand| matches!(operand, Operand::Long(_))) {4} else {2}
+            }
+            Statement::Bytes(values) => values.len() as u16,
+            Statement::Words(values) => 2 * values.len() as u16,
+            Statement::Sprite(bytes) => bytes.len() as u16
+        }
+    }
+}
+
+//assembles mnemonic source into a ROM image loaded at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    assemble_with_map(source).map(|(rom, _)| rom)
+}
+
+//assembles source and records the address of every instruction line and label
+pub fn assemble_with_map(source: &str) -> Result<(Vec<u8>, SourceMap), AssemblerError> {
+    let mut symbols: HashMap<String, u16> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = ORIGIN;
+    let mut map = SourceMap::default();
+
+    //first pass, lay out statements and record labels and constants
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let error = |message: String| AssemblerError{line: number, message};
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                return Err(error(format!("invalid label '{}'", label)));
+            }
+            define(&mut symbols, label, address).map_err(error)?;
+            map.labels.insert(address, label.to_string());
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (keyword, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, "")
+        };
+        let statement = match keyword.to_ascii_lowercase().as_str() {
+            "db" => Statement::Bytes(split_list(rest)),
+            "dw" => Statement::Words(split_list(rest)),
+            "sprite" => Statement::Sprite(parse_sprite(rest).map_err(error)?),
+            _ => {
+                //NAME equ VALUE
+                let mut words = rest.split_whitespace();
+                if words.next().map(|word| word.eq_ignore_ascii_case("equ")) == Some(true) {
+                    let value = words.collect::<Vec<&str>>().join(" ");
+                    let value = resolve(&symbols, &value).map_err(error)?;
+                    define(&mut symbols, keyword, value).map_err(error)?;
+                    continue;
+                }
+                Statement::Instruction{
+                    mnemonic: keyword.to_ascii_uppercase(),
+                    operands: parse_operands(rest)
+                }
+            }
+        };
+        let length = statement.length();
+        if let Statement::Instruction{..} = statement {
+            map.lines.insert(number, address);
+        }
+        statements.push((number, address, statement));
+        address = address.checked_add(length).ok_or_else(|| error(String::from("program does not fit in memory")))?;
+    }
+
+    //second pass, resolve operands and emit bytes
+    let mut rom = Vec::new();
+    for (number, _, statement) in statements {
+        let error = |message: String| AssemblerError{line: number, message};
+        match statement {
+            Statement::Instruction{mnemonic, operands} => {
+                let instruction = encode(&symbols, &mnemonic, &operands).map_err(error)?;
+                rom.extend(instruction.to_bytes());
+            }
+            Statement::Bytes(values) => {
+                for value in values {
+                    rom.push(limit(resolve(&symbols, &value).map_err(error)?, 0xFF).map_err(error)? as u8);
+                }
+            }
+            Statement::Words(values) => {
+                for value in values {
+                    rom.extend(resolve(&symbols, &value).map_err(error)?.to_be_bytes().iter());
+                }
+            }
+            Statement::Sprite(bytes) => rom.extend(bytes)
+        }
+    }
+    Ok((rom, map))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false
+    }
+}
+
+fn define(symbols: &mut HashMap<String, u16>, name: &str, value: u16) -> Result<(), String> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("'{}' is defined more than once", name));
+    }
+    Ok(())
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+}
+
+//a row of '#' and '.' pixels, 8 or 16 wide
+fn parse_sprite(text: &str) -> Result<Vec<u8>, String> {
+    let row = text.trim_matches('"');
+    if row.len() != 8 && row.len() != 16 {
+        return Err(format!("sprite rows must be 8 or 16 pixels wide, not {}", row.len()));
+    }
+    let mut bits: u16 = 0;
+    for pixel in row.chars() {
+        bits = match pixel {
+            '#' | 'X' | 'x' | '1' => (bits << 1) | 1,
+            '.' | '_' | '0' => bits << 1,
+            _ => return Err(format!("unexpected pixel '{}' in sprite", pixel))
+        };
+    }
+    if row.len() == 8 {
+        Ok(vec![bits as u8])
+    } else {
+        Ok(bits.to_be_bytes().to_vec())
+    }
+}
+
+fn parse_operands(text: &str) -> Vec<Operand> {
+    let mut operands = Vec::new();
+    for item in split_list(text) {
+        //SAVE and LOAD take a register range written as VX - VY
+        let parts: Vec<&str> = if item.to_ascii_uppercase().starts_with('V') && item.contains('-') {
+            item.split('-').collect()
+        } else {
+            vec![item.as_str()]
+        };
+        for part in parts {
+            operands.push(parse_operand(part.trim()));
+        }
+    }
+    operands
+}
+
+fn parse_operand(text: &str) -> Operand {
+    let upper = text.to_ascii_uppercase();
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(register) = u8::from_str_radix(&upper[1..], 16) {
+            return Operand::V(register);
+        }
+    }
+    if let Some(rest) = upper.strip_prefix("LONG ") {
+        return Operand::Long(text[text.len() - rest.len()..].trim().to_string());
+    }
+    match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => Operand::Value(text.to_string())
+    }
+}
+
+pub(crate) fn parse_number(text: &str) -> Option<u32> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn resolve(symbols: &HashMap<String, u16>, text: &str) -> Result<u16, String> {
+    if let Some(value) = symbols.get(text) {
+        return Ok(*value);
+    }
+    match parse_number(text) {
+        Some(value) if value <= 0xFFFF => Ok(value as u16),
+        Some(_) => Err(format!("{} does not fit in 16 bits", text)),
+        None if is_identifier(text) => Err(format!("undefined symbol '{}'", text)),
+        None => Err(format!("invalid number '{}'", text))
+    }
+}
+
+fn limit(value: u16, max: u16) -> Result<u16, String> {
+    if value > max {
+        return Err(format!("0x{:X} is larger than 0x{:X}", value, max));
+    }
+    Ok(value)
+}
+
+fn encode(symbols: &HashMap<String, u16>, mnemonic: &str, operands: &[Operand]) -> Result<Instruction, String> {
+    use Instruction::*;
+    use Operand::*;
+
+    let value = |text: &String, max: u16| resolve(symbols, text).and_then(|value| limit(value, max));
+    let address = |text: &String| value(text, 0xFFF);
+    let byte = |text: &String| value(text, 0xFF).map(|value| value as u8);
+    let nibble = |text: &String| value(text, 0xF).map(|value| value as u8);
+
+    let instruction = match (mnemonic, operands) {
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("SYS", [Value(nnn)]) => Sys(address(nnn)?),
+        ("SCD", [Value(n)]) => Scd(nibble(n)?),
+        ("SCR", []) => Scr,
+        ("SCL", []) => Scl,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => Low,
+        ("HIGH", []) => High,
+        ("JP", [Value(nnn)]) => Jp(address(nnn)?),
+        ("JP", [V(0), Value(nnn)]) => JpV0(address(nnn)?),
+        ("CALL", [Value(nnn)]) => Call(address(nnn)?),
+        ("SE", [V(x), V(y)]) => SeReg{x: *x, y: *y},
+        ("SE", [V(x), Value(kk)]) => SeImm{x: *x, kk: byte(kk)?},
+        ("SNE", [V(x), V(y)]) => SneReg{x: *x, y: *y},
+        ("SNE", [V(x), Value(kk)]) => SneImm{x: *x, kk: byte(kk)?},
+        ("SAVE", [V(x), V(y)]) => Save{x: *x, y: *y},
+        ("LOAD", [V(x), V(y)]) => Load{x: *x, y: *y},
+        ("LD", [V(x), V(y)]) => LdReg{x: *x, y: *y},
+        ("LD", [V(x), Dt]) => LdVxDt{x: *x},
+        ("LD", [V(x), K]) => LdVxK{x: *x},
+        ("LD", [V(x), IndirectI]) => LdVxI{x: *x},
+        ("LD", [V(x), R]) => LdVxR{x: *x},
+        ("LD", [V(x), Value(kk)]) => LdImm{x: *x, kk: byte(kk)?},
+        ("LD", [I, Long(nnnn)]) => LdILong(value(nnnn, 0xFFFF)?),
+        ("LD", [I, Value(nnn)]) => LdI(address(nnn)?),
+        ("LD", [Dt, V(x)]) => LdDtVx{x: *x},
+        ("LD", [St, V(x)]) => LdStVx{x: *x},
+        ("LD", [F, V(x)]) => LdF{x: *x},
+        ("LD", [Hf, V(x)]) => LdHf{x: *x},
+        ("LD", [B, V(x)]) => LdB{x: *x},
+        ("LD", [IndirectI, V(x)]) => LdIVx{x: *x},
+        ("LD", [R, V(x)]) => LdRVx{x: *x},
+        ("ADD", [V(x), V(y)]) => AddReg{x: *x, y: *y},
+        ("ADD", [V(x), Value(kk)]) => AddImm{x: *x, kk: byte(kk)?},
+        ("ADD", [I, V(x)]) => AddI{x: *x},
+        ("OR", [V(x), V(y)]) => Or{x: *x, y: *y},
+        ("AND", [V(x), V(y)]) => And{x: *x, y: *y},
+        ("XOR", [V(x), V(y)]) => Xor{x: *x, y: *y},
+        ("SUB", [V(x), V(y)]) => Sub{x: *x, y: *y},
+        ("SUBN", [V(x), V(y)]) => Subn{x: *x, y: *y},
+        ("SHR", [V(x)]) => Shr{x: *x, y: *x},
+        ("SHR", [V(x), V(y)]) => Shr{x: *x, y: *y},
+        ("SHL", [V(x)]) => Shl{x: *x, y: *x},
+        ("SHL", [V(x), V(y)]) => Shl{x: *x, y: *y},
+        ("RND", [V(x), Value(kk)]) => Rnd{x: *x, kk: byte(kk)?},
+        ("DRW", [V(x), V(y), Value(n)]) => Drw{x: *x, y: *y, n: nibble(n)?},
+        ("SKP", [V(x)]) => Skp{x: *x},
+        ("SKNP", [V(x)]) => Sknp{x: *x},
+        ("PLANE", [Value(n)]) => Plane(nibble(n)?),
+        ("AUDIO", []) => Audio,
+        ("PITCH", [V(x)]) => Pitch{x: *x},
+        _ => return Err(format!("unknown instruction or operands for '{}'", mnemonic))
+    };
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Disassembly;
+
+    #[test]
+    fn instructions_test() {
+        let rom = assemble("CLS\nld va, 0x22\nDRW V1, V2, 5\nLD [I], VF\nSAVE V2 - V4\nLD I, LONG 0xBEEF").unwrap();
+        assert_eq!(vec![0x00, 0xE0, 0x6A, 0x22, 0xD1, 0x25, 0xFF, 0x55, 0x52, 0x42, 0xF0, 0x00, 0xBE, 0xEF], rom);
+    }
+
+    #[test]
+    fn labels_and_constants_test() {
+        let source = "
+            speed equ 3
+            start:
+                ADD V0, speed   ; move
+                CALL draw
+                JP start
+            draw: LD I, ball
+                RET
+            ball:
+                sprite ..####..
+                db 0b00011000, 24
+                dw 0x1234";
+        let rom = assemble(source).unwrap();
+        assert_eq!(vec![0x70, 0x03, 0x22, 0x06, 0x12, 0x00, 0xA2, 0x0A, 0x00, 0xEE,
+                        0x3C, 0x18, 0x18, 0x12, 0x34], rom);
+    }
+
+    #[test]
+    fn errors_test() {
+        assert_eq!(Err(AssemblerError{line: 2, message: String::from("undefined symbol 'nowhere'")}),
+                   assemble("CLS\nJP nowhere"));
+        assert_eq!(2, assemble("LD V0, 1\nLD V0, 0x100").unwrap_err().line);
+        assert_eq!(1, assemble("MOV V0, V1").unwrap_err().line);
+        assert_eq!(2, assemble("a: CLS\na: CLS").unwrap_err().line);
+    }
+
+    #[test]
+    fn disassembly_round_trip_test() {
+        let rom = [0x22, 0x08, 0x12, 0x02, 0x3C, 0x42, 0x3C, 0x00, 0xA2, 0x04,
+                   0x30, 0x01, 0xF0, 0x00, 0x12, 0x34, 0xD0, 0x13, 0x00, 0xEE];
+        let listing = Disassembly::new(&rom, 0x200).to_string();
+        assert_eq!(rom.to_vec(), assemble(&listing).unwrap());
+    }
+
+    #[test]
+    fn source_map_test() {
+        let (_, map) = assemble_with_map("start:\n    CLS\n    LD I, LONG data\nloop: JP loop\ndata: db 1").unwrap();
+        assert_eq!(Some(0x200), map.address_of_line(2));
+        assert_eq!(Some(0x206), map.address_of_line(4));
+        assert_eq!(None, map.address_of_line(5));
+        assert_eq!(Some(3), map.line_of_address(0x202));
+        assert_eq!(Some("loop"), map.label_at(0x206));
+        assert_eq!(Some(("start", 0x200)), map.label_before(0x204));
+        assert_eq!(Ok(map.clone()), SourceMap::parse(&map.to_string()));
+        let mut map = map;
+        map.set_source(String::from("/games/my pong.asm"));
+        assert_eq!(Some("/games/my pong.asm"), SourceMap::parse(&map.to_string()).unwrap().source());
+        assert!(SourceMap::parse("line two 0x200").is_err());
+    }
+}
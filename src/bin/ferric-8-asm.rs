@@ -0,0 +1,55 @@
+use ferric_8::assembler::assemble_with_map;
+
+use std::env::args;
+use std::fs::{canonicalize, read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+fn main() {
+    let mut args: Vec<String> = args().collect();
+    //--map also writes a source map next to the ROM, for source level debugging
+    let write_map = match args.iter().position(|arg| arg == "--map") {
+        Some(position) => {
+            args.remove(position);
+            true
+        }
+        None => false
+    };
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: ferric-8-asm [--map] path/to/source.asm [path/to/rom.ch8]");
+        exit(1);
+    }
+    let source_path = &args[1];
+    let rom_path = match args.get(2) {
+        Some(rom_path) => rom_path.clone(),
+        None => Path::new(source_path).with_extension("ch8").to_string_lossy().into_owned()
+    };
+    let source = match read_to_string(source_path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", source_path, error);
+            exit(1);
+        }
+    };
+    let (rom, mut map) = match assemble_with_map(&source) {
+        Ok(assembled) => assembled,
+        Err(error) => {
+            eprintln!("{}:{}", source_path, error);
+            exit(1);
+        }
+    };
+    if let Err(error) = write(&rom_path, &rom) {
+        eprintln!("Could not write {}: {}", rom_path, error);
+        exit(1);
+    }
+    if write_map {
+        let map_path = Path::new(&rom_path).with_extension("map");
+        //editors identify sources by absolute path
+        let source = canonicalize(source_path).unwrap_or_else(|_| PathBuf::from(source_path));
+        map.set_source(source.to_string_lossy().into_owned());
+        if let Err(error) = write(&map_path, map.to_string()) {
+            eprintln!("Could not write {}: {}", map_path.display(), error);
+            exit(1);
+        }
+    }
+}
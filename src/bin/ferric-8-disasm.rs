@@ -0,0 +1,23 @@
+use ferric_8::disassembler::Disassembly;
+
+use std::env::args;
+use std::fs::read;
+use std::process::exit;
+
+fn main() {
+    let filename = match args().nth(1) {
+        Some(filename) => filename,
+        None => {
+            eprintln!("Usage: ferric-8-disasm path/to/rom.ch8");
+            exit(1);
+        }
+    };
+    let rom = match read(&filename) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", filename, error);
+            exit(1);
+        }
+    };
+    print!("{}", Disassembly::new(&rom, 0x200));
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+//addresses to stop at and whatever each one needs to trigger, shared by the debugger, gdb stub and debug adapter
+pub struct Breakpoints<T> {
+    addresses: BTreeMap<u16, T>,
+    //a breakpoint being continued from must not trigger again straight away
+    resume_address: Option<u16>
+}
+
+impl<T> Breakpoints<T> {
+    pub fn new() -> Breakpoints<T> {
+        Breakpoints { addresses: BTreeMap::new(), resume_address: None }
+    }
+
+    pub fn insert(&mut self, address: u16, value: T) -> Option<T> {
+        self.addresses.insert(address, value)
+    }
+
+    pub fn remove(&mut self, address: u16) -> Option<T> {
+        self.addresses.remove(&address)
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+    }
+
+    //drops the breakpoints keep returns false for, it may also change the ones it keeps
+    pub fn retain<F: FnMut(&mut T) -> bool>(&mut self, mut keep: F) {
+        self.addresses.retain(|_, value| keep(value));
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.addresses.contains_key(&address)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &u16> {
+        self.addresses.keys()
+    }
+
+    //call when execution continues from pc
+    pub fn resume(&mut self, pc: u16) {
+        self.resume_address = Some(pc);
+    }
+
+    //whether to stop before the instruction at pc, triggers decides for breakpoints with a condition
+    //call before every instruction, the breakpoint just resumed from is skipped once
+    pub fn hit<F: FnOnce(&T) -> bool>(&mut self, pc: u16, triggers: F) -> bool {
+        let resuming = self.resume_address.take() == Some(pc);
+        !resuming && self.addresses.get(&pc).is_some_and(triggers)
+    }
+}
+
+impl<T: Default> Breakpoints<T> {
+    //the breakpoint at address, set to the default value if there was none
+    pub fn entry(&mut self, address: u16) -> &mut T {
+        self.addresses.entry(address).or_default()
+    }
+}
+
+impl<T> Default for Breakpoints<T> {
+    fn default() -> Breakpoints<T> {
+        Breakpoints::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_test() {
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.insert(0x202, ());
+        assert!(!breakpoints.hit(0x200, |_| true));
+        assert!(breakpoints.hit(0x202, |_| true));
+        assert!(breakpoints.hit(0x202, |_| true));
+        breakpoints.resume(0x202);
+        assert!(!breakpoints.hit(0x202, |_| true));
+        assert!(breakpoints.hit(0x202, |_| true));
+        //resuming only skips the address it resumed from, and only once
+        breakpoints.resume(0x200);
+        assert!(!breakpoints.hit(0x200, |_| true));
+        assert!(breakpoints.hit(0x202, |_| true));
+    }
+
+    #[test]
+    fn condition_test() {
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.insert(0x200, 3);
+        assert!(!breakpoints.hit(0x200, |&value| value > 5));
+        breakpoints.insert(0x200, 8);
+        breakpoints.insert(0x204, 1);
+        assert!(breakpoints.hit(0x200, |&value| value > 5));
+        assert_eq!(vec![&0x200, &0x204], breakpoints.addresses().collect::<Vec<_>>());
+        assert_eq!(Some(8), breakpoints.remove(0x200));
+        assert!(!breakpoints.contains(0x200));
+    }
+
+    #[test]
+    fn entry_test() {
+        let mut breakpoints = Breakpoints::<u8>::new();
+        *breakpoints.entry(0x200) += 5;
+        *breakpoints.entry(0x200) += 1;
+        *breakpoints.entry(0x204) += 1;
+        breakpoints.retain(|value| {
+            *value -= 1;
+            *value > 0
+        });
+        assert_eq!(vec![&0x200], breakpoints.addresses().collect::<Vec<_>>());
+        assert!(breakpoints.hit(0x200, |&value| value == 5));
+    }
+}
@@ -5,8 +5,11 @@ pub struct ByteRegister {
 
 impl ByteRegister {
     pub fn new(name: String) -> ByteRegister {
-        let mut reg : ByteRegister = ByteRegister { value: 0, name};
-        reg
+        ByteRegister { value: 0, name}
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
     pub fn read_reg(&self) -> u8 {
@@ -1,9 +1,17 @@
-use crate::ram::RAM;
+use crate::ram::{RAM, MEMORY_SIZE, ORIGINAL_MEMORY_SIZE};
 use crate::byte_register::ByteRegister;
 use crate::word_register::WordRegister;
-use crate::graphics_buffer::GraphicsBuffer;
-use rand;
-use rand::Rng;
+use crate::graphics_buffer::{GraphicsBuffer, PLANES};
+use crate::quirks::Quirks;
+use crate::instruction::Instruction;
+use crate::error::EmulatorError;
+use crate::save_state::{invalid, StateReader, StateWriter};
+use crate::rng::{RandomSource, Xorshift};
+use crate::timing::{outcome_cycles, vip_cycles, Outcome};
+use crate::idle::{IdleDetector, IdleLoop, Registers};
+
+//square wave used until a ROM loads its own audio pattern
+pub const BUZZER_PATTERN: [u8; 16] = [0xF0; 16];
 
 pub struct CPU {
     v: [ByteRegister; 16],
@@ -14,15 +22,31 @@ pub struct CPU {
     sound_timer: ByteRegister,
     delay_timer: ByteRegister,
     ram: RAM,
+    quirks: Quirks,
+    //address and opcode of the instruction being executed, for error reports
+    current_address: u16,
+    current_opcode: u16,
+    rpl_flags: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    rng: Box<dyn RandomSource>,
+    //set by each vertical blank and cleared by the next draw, for the display wait quirk
+    vblank: bool,
+    display_waiting: bool,
+    idle: IdleDetector,
+    //set when the last instruction finished a pass around a loop that changed nothing
+    idle_loop: Option<IdleLoop>,
     pub draw_flag: bool,
     pub sound_flag: bool,
+    pub audio_flag: bool,
+    pub exit_flag: bool,
     pub keys_pressed: [bool; 16]
 }
 
 impl CPU{
-    pub fn new(ram: RAM) -> CPU{
+    pub fn new(ram: RAM, quirks: Quirks) -> CPU{
         //Regs from V0 to VF
-        let mut v_regs:[ByteRegister; 16] =
+        let v_regs:[ByteRegister; 16] =
             [ByteRegister::new(String::from("V0")),
             ByteRegister::new(String::from("V1")),
             ByteRegister::new(String::from("V2")),
@@ -40,7 +64,7 @@ impl CPU{
             ByteRegister::new(String::from("VE")),
             ByteRegister::new(String::from("VF"))];
         //stack regs
-        let mut stack_regs:[WordRegister; 17] = [WordRegister::new(String::from("dummy")),
+        let stack_regs:[WordRegister; 17] = [WordRegister::new(String::from("dummy")),
             WordRegister::new(String::from("Stack0")),
             WordRegister::new(String::from("Stack1")),
             WordRegister::new(String::from("Stack2")),
@@ -61,6 +85,17 @@ impl CPU{
 
         let mut cpu = CPU{
             ram,
+            quirks,
+            current_address: 0,
+            current_opcode: 0,
+            rpl_flags: [0; 16],
+            audio_pattern: BUZZER_PATTERN,
+            pitch: 64,
+            rng: Box::new(Xorshift::from_entropy()),
+            vblank: false,
+            display_waiting: false,
+            idle: IdleDetector::new(),
+            idle_loop: None,
             v: v_regs,
             index: WordRegister::new(String::from("Index")),
             program_counter: WordRegister::new(String::from("Program Counter")),
@@ -70,93 +105,193 @@ impl CPU{
             sound_timer: ByteRegister::new(String::from("Sound timer")),
             draw_flag: false,
             sound_flag: false,
+            audio_flag: false,
+            exit_flag: false,
             keys_pressed: [false; 16]
         };
         cpu.program_counter.write_reg(0x200);
         cpu
     }
 
-    pub fn cycle(&mut self, gfx: &mut GraphicsBuffer){
+    //executes one instruction and returns the VIP machine cycles it took
+    pub fn cycle(&mut self, gfx: &mut GraphicsBuffer) -> Result<u32, EmulatorError>{
         //fetch
         let curr_address = self.program_counter.read_reg();
-        let instruction = self.ram.read_word(curr_address);
+        self.current_address = curr_address;
+        self.current_opcode = 0;
+        let opcode_address = self.memory_address(curr_address, 1)?;
+        let opcode = self.ram.read_word(curr_address);
+        self.current_opcode = opcode;
+        let next_word = self.ram.read_word(opcode_address.wrapping_add(1));
         self.program_counter.next_instruction();
 
-        //nibbles
-        let op_1 = (instruction & 0xF000) >> 12;
-        let op_2 = (instruction & 0x0F00) >> 8;
-        let op_3 = (instruction & 0x00F0) >> 4;
-        let op_4 = instruction & 0x000F;
-
-        //commonly encountered expressions
-        let x = op_2 as usize;
-        let vx = self.v[x].read_reg();
-        let y = op_3 as usize;
-        let vy = self.v[y].read_reg();
-        let n = op_4 as u8;
-        let kk = (instruction & 0x00FF) as u8;
-        let nnn = instruction & 0x0FFF;
-
         //decode/exec
-        match (op_1, op_2, op_3, op_4){
-            (0, 0, 0, 0) => {}
-            (0, 0, 0xE, 0) => {
+        let instruction = Instruction::decode_with_next(opcode, next_word)
+            .ok_or(EmulatorError::InvalidOpcode{pc: curr_address, opcode})?;
+        let cycles = vip_cycles(&instruction, self);
+        let index = self.index.read_reg();
+        self.execute(instruction, gfx)?;
+        let next_address = self.program_counter.read_reg();
+        let cycles = cycles + outcome_cycles(&instruction, Outcome{
+            skipped: next_address != curr_address.wrapping_add(2),
+            page_crossed: (index ^ self.index.read_reg()) & 0xFF00 != 0
+        });
+
+        //a jump back, or an instruction waiting on itself, may have closed a busy wait
+        self.idle.observe(&instruction, cycles);
+        self.idle_loop = if next_address <= curr_address {
+            let registers = self.idle_registers();
+            self.idle.loop_back(curr_address, next_address, registers)
+        } else {
+            None
+        };
+        Ok(cycles)
+    }
+
+    fn execute(&mut self, instruction: Instruction, gfx: &mut GraphicsBuffer) -> Result<(), EmulatorError>{
+        use Instruction::*;
+
+        match instruction{
+            //0000 is left as a no-op, machine code routines cannot run here
+            Sys(0) => {}
+            Sys(_) => {
+                return Err(EmulatorError::InvalidOpcode{pc: self.current_address, opcode: self.current_opcode});
+            }
+            Cls => {
                 gfx.clear();
                 self.draw_flag = true;
             }
-            (0, 0, 0xE, 0xE) => {
+            Ret => {
+                if self.stack_pointer.read_reg() == 0{
+                    return Err(EmulatorError::StackUnderflow{pc: self.current_address, opcode: self.current_opcode});
+                }
                 self.program_counter.write_reg(self.stack[self.stack_pointer.read_reg() as usize].read_reg());
                 self.stack_pointer.decrement_reg();
             }
-            (1, _, _, _) => {
+            Scd(n) => {
+                gfx.scroll_down(n as usize);
+                self.draw_flag = true;
+            }
+            Scr => {
+                gfx.scroll_right(4);
+                self.draw_flag = true;
+            }
+            Scl => {
+                gfx.scroll_left(4);
+                self.draw_flag = true;
+            }
+            Exit => {
+                self.exit_flag = true;
+            }
+            Low => {
+                gfx.set_hires(false);
+                self.draw_flag = true;
+            }
+            High => {
+                gfx.set_hires(true);
+                self.draw_flag = true;
+            }
+            Jp(nnn) => {
                 self.program_counter.write_reg(nnn);
             }
-            (2, _, _, _) => {
+            Call(nnn) => {
+                if self.stack_pointer.read_reg() as usize == self.stack.len() - 1{
+                    return Err(EmulatorError::StackOverflow{pc: self.current_address, opcode: self.current_opcode});
+                }
                 self.stack_pointer.increment_reg();
                 self.stack[self.stack_pointer.read_reg() as usize].write_reg(self.program_counter.read_reg());
                 self.program_counter.write_reg(nnn);
             }
-            (3, _, _, _) => {
+            SeImm{x, kk} => {
+                let vx = self.v[x as usize].read_reg();
                 if vx == kk{
-                    self.program_counter.next_instruction();
+                    self.skip_instruction();
                 }
             }
-            (4, _, _, _) => {
+            SneImm{x, kk} => {
+                let vx = self.v[x as usize].read_reg();
                 if vx != kk{
-                    self.program_counter.next_instruction();
+                    self.skip_instruction();
                 }
             }
-            (5, _, _, 0) => {
+            SeReg{x, y} => {
+                let vx = self.v[x as usize].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 if vx == vy{
-                    self.program_counter.next_instruction();
+                    self.skip_instruction();
                 }
             }
-            (6, _, _, _) => {
+            Save{x, y} => {
+                let x = x as usize;
+                let y = y as usize;
+                let location = self.index.read_reg();
+                for (offset, i) in CPU::register_range(x, y).enumerate(){
+                    self.ram.write_byte(self.memory_address(location, offset)?, self.v[i].read_reg());
+                }
+            }
+            Load{x, y} => {
+                let x = x as usize;
+                let y = y as usize;
+                let location = self.index.read_reg();
+                for (offset, i) in CPU::register_range(x, y).enumerate(){
+                    self.v[i].write_reg(self.ram.read_byte(self.memory_address(location, offset)?));
+                }
+            }
+            LdImm{x, kk} => {
+                let x = x as usize;
                 self.v[x].write_reg(kk);
             }
-            (7, _, _, _) => {
+            AddImm{x, kk} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
                 self.v[x].write_reg(u8::wrapping_add(vx,  kk ));
             }
-            (8, _, _, 0) => {
+            LdReg{x, y} => {
+                let x = x as usize;
+                let vy = self.v[y as usize].read_reg();
                 self.v[x].write_reg(vy);
             }
-            (8, _, _, 1) => {
+            Or{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 self.v[x].write_reg(vx | vy);
+                if self.quirks.vf_reset{
+                    self.v[0xF].write_reg(0);
+                }
             }
-            (8, _, _, 2) => {
+            And{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 self.v[x].write_reg(vx & vy);
+                if self.quirks.vf_reset{
+                    self.v[0xF].write_reg(0);
+                }
             }
-            (8, _, _, 3) => {
+            Xor{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 self.v[x].write_reg(vx ^ vy);
+                if self.quirks.vf_reset{
+                    self.v[0xF].write_reg(0);
+                }
             }
-            (8, _, _, 4) => {
+            AddReg{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 let sum = vx as u16 + vy as u16;
                 if sum & 0x0100 != 0{
                     self.v[0xF].write_reg(1);
                 }
                 self.v[x].write_reg((sum & 0xFF) as u8);
             }
-            (8, _, _, 5) => {
+            Sub{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 if vx >= vy{
                     self.v[0xF].write_reg(1);
                 } else {
@@ -164,15 +299,22 @@ impl CPU{
                 }
                 self.v[x].write_reg(u8::wrapping_sub(vx, vy));
             }
-            (8, _, _, 6) => {
-                if vx & 1 != 0 {
+            Shr{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
+                let value = if self.quirks.shift {vx} else {vy};
+                if value & 1 != 0 {
                     self.v[0xF].write_reg(1);
                 } else {
                     self.v[0xF].write_reg(0);
                 }
-                self.v[x].write_reg(vx >> 1);
+                self.v[x].write_reg(value >> 1);
             }
-            (8, _, _, 7) => {
+            Subn{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 if vx <= vy{
                     self.v[0xF].write_reg(1);
                 } else {
@@ -180,64 +322,126 @@ impl CPU{
                 }
                 self.v[x].write_reg(u8::wrapping_sub(vy, vx));
             }
-            (8, _, _, 0xE) => {
-                if vx & 0x80 != 0 {
+            Shl{x, y} => {
+                let x = x as usize;
+                let vx = self.v[x].read_reg();
+                let vy = self.v[y as usize].read_reg();
+                let value = if self.quirks.shift {vx} else {vy};
+                if value & 0x80 != 0 {
                     self.v[0xF].write_reg(1);
                 } else {
                     self.v[0xF].write_reg(0);
                 }
-                self.v[x].write_reg(vx << 1);
+                self.v[x].write_reg(value << 1);
             }
-            (9, _, _, 0) => {
+            SneReg{x, y} => {
+                let vx = self.v[x as usize].read_reg();
+                let vy = self.v[y as usize].read_reg();
                 if vx != vy{
-                    self.program_counter.next_instruction();
+                    self.skip_instruction();
                 }
             }
-            (0xA, _, _, _) => {
+            LdI(nnn) => {
                 self.index.write_reg(nnn);
             }
-            (0xB, _, _, _) => {
-                self.program_counter.write_reg(nnn + self.v[0].read_reg() as u16);
+            JpV0(nnn) => {
+                //with the jump quirk the high nibble of the address selects the register
+                let register = if self.quirks.jump {(nnn >> 8) as usize} else {0};
+                self.program_counter.write_reg(nnn + self.v[register].read_reg() as u16);
             }
-            (0xC, _, _, _) => {
-                let mut rng = rand::thread_rng();
-                let ran_u8: u8 = rng.gen();
+            Rnd{x, kk} => {
+                let x = x as usize;
+                let ran_u8 = self.rng.next_byte();
                 self.v[x].write_reg(ran_u8 & kk);
             }
-            (0xD, _, _, _) => {
-                let x_coord = vx & 0x3F;
-                let y_coord = vy & 0x1F;
+            Drw{x, y, n} => {
+                //with the display wait quirk a draw uses up the vertical blank, and later draws stall until the next one
+                if self.quirks.display_wait{
+                    if !self.vblank{
+                        self.program_counter.wait_instruction();
+                        self.display_waiting = true;
+                        return Ok(());
+                    }
+                    self.vblank = false;
+                }
+                let vx = self.v[x as usize].read_reg();
+                let vy = self.v[y as usize].read_reg();
+                let width = gfx.width() as u8;
+                let height = gfx.height() as u8;
+                let x_coord = vx % width;
+                let y_coord = vy % height;
+                //DXY0 draws a 16x16 sprite made of two bytes per row
+                let (rows, columns) = if n == 0 {(16, 16)} else {(n, 8)};
+                let bytes_per_row = columns as usize / 8;
                 self.v[0xF].write_reg(0);
-                for row in 0..n{
-                    //note that each row of the sprite is 8 pixels, and thus one byte long
-                    let this_row = self.ram.read_byte(self.index.read_reg() + (row) as u16);
-                    if y_coord + row <= 0x1F{
-                        for column in 0..8{
-                            if x_coord + column <= 0x3F && (this_row & (0x80 >> column)) != 0{
-                                if gfx.get(x_coord + column, y_coord + row){
+                //each selected plane takes the next sprite's worth of bytes from I
+                let location = self.index.read_reg();
+                let mut sprite_offset = 0;
+                for plane in 0..PLANES{
+                    if gfx.selected_planes() & (1 << plane) == 0{
+                        continue;
+                    }
+                    for row in 0..rows{
+                        //left aligned so bit 15 is always the leftmost pixel
+                        let mut this_row: u16 = 0;
+                        for byte in 0..bytes_per_row{
+                            let offset = sprite_offset + row as usize * bytes_per_row + byte;
+                            this_row |= (self.ram.read_byte(self.memory_address(location, offset)?) as u16) << (8 - 8 * byte);
+                        }
+                        if y_coord + row >= height && self.quirks.clipping{
+                            continue;
+                        }
+                        let pixel_y = (y_coord + row) % height;
+                        for column in 0..columns{
+                            if x_coord + column >= width && self.quirks.clipping{
+                                continue;
+                            }
+                            let pixel_x = (x_coord + column) % width;
+                            if (this_row & (0x8000 >> column)) != 0{
+                                if gfx.get_plane(plane, pixel_x, pixel_y){
                                     self.v[0xF].write_reg(1);
                                 }
-                                gfx.toggle(x_coord + column, y_coord + row);
+                                gfx.toggle_plane(plane, pixel_x, pixel_y);
                             }
                         }
                     }
+                    sprite_offset += rows as usize * bytes_per_row;
                 }
                 self.draw_flag = true;
             }
-            (0xE, _, 9, 0xE) => {
-                if self.keys_pressed[self.v[x].read_reg() as usize] {
-                    self.program_counter.next_instruction();
+            Skp{x} => {
+                let x = x as usize;
+                if self.keys_pressed[self.v[x].read_reg() as usize & 0xF] {
+                    self.skip_instruction();
                 };
             }
-            (0xE, _, 0xA, 1) => {
-                if !self.keys_pressed[self.v[x].read_reg() as usize] {
-                    self.program_counter.next_instruction();
+            Sknp{x} => {
+                let x = x as usize;
+                if !self.keys_pressed[self.v[x].read_reg() as usize & 0xF] {
+                    self.skip_instruction();
                 };
             }
-            (0xF, _, 0, 7) => {
+            LdILong(address) => {
+                //step over the address stored in the word following the instruction
+                self.index.write_reg(address);
+                self.program_counter.next_instruction();
+            }
+            Plane(n) => {
+                gfx.select_planes(n);
+            }
+            Audio => {
+                let location = self.index.read_reg();
+                for i in 0..16{
+                    self.audio_pattern[i] = self.ram.read_byte(self.memory_address(location, i)?);
+                }
+                self.audio_flag = true;
+            }
+            LdVxDt{x} => {
+                let x = x as usize;
                 self.v[x].write_reg(self.delay_timer.read_reg());
             }
-            (0xF, _, 0, 0xA) => {
+            LdVxK{x} => {
+                let x = x as usize;
                 self.program_counter.wait_instruction();
                 for i in 0..0x10{
                     if self.keys_pressed[i]{
@@ -247,41 +451,284 @@ impl CPU{
                     }
                 }
             }
-            (0xF, _, 1, 5) => {
+            LdDtVx{x} => {
+                let vx = self.v[x as usize].read_reg();
                 self.delay_timer.write_reg(vx);
             }
-            (0xF, _, 1, 8) => {
+            LdStVx{x} => {
+                let vx = self.v[x as usize].read_reg();
                 self.sound_timer.write_reg(vx);
             }
-            (0xF, _, 1, 0xE) => {
-                self.index.write_reg(self.index.read_reg() + vx as u16);
+            AddI{x} => {
+                let vx = self.v[x as usize].read_reg();
+                self.index.write_reg(self.index.read_reg().wrapping_add(vx as u16));
             }
-            (0xF, _, 2, 9) => {
+            LdF{x} => {
+                let vx = self.v[x as usize].read_reg();
                 self.index.write_reg(0x50 + (5 * vx as u16));
             }
-            (0xF, _, 3, 3) => {
+            LdHf{x} => {
+                let vx = self.v[x as usize].read_reg();
+                self.index.write_reg(0xA0 + (10 * vx as u16));
+            }
+            Pitch{x} => {
+                let vx = self.v[x as usize].read_reg();
+                self.pitch = vx;
+                self.audio_flag = true;
+            }
+            LdB{x} => {
+                let vx = self.v[x as usize].read_reg();
                 let location = self.index.read_reg();
-                self.ram.write_byte(location, vx/100);
-                self.ram.write_byte(location + 1, (vx/10)%10);
-                self.ram.write_byte(location + 2, vx%10);
+                self.ram.write_byte(self.memory_address(location, 0)?, vx/100);
+                self.ram.write_byte(self.memory_address(location, 1)?, (vx/10)%10);
+                self.ram.write_byte(self.memory_address(location, 2)?, vx%10);
             }
-            (0xF, _, 5, 5) => {
+            LdIVx{x} => {
+                let x = x as usize;
                 let location = self.index.read_reg();
                 for i  in 0..(x+1){
-                    self.ram.write_byte(location + i as u16, self.v[i].read_reg());
+                    self.ram.write_byte(self.memory_address(location, i)?, self.v[i].read_reg());
+                }
+                if self.quirks.load_store{
+                    self.index.write_reg(location.wrapping_add(x as u16 + 1));
                 }
             }
-            (0xF, _, 6, 5) => {
+            LdVxI{x} => {
+                let x = x as usize;
                 let location = self.index.read_reg();
                 for i  in 0..(x+1){
-                    self.v[i].write_reg(self.ram.read_byte(location + i as u16));
+                    self.v[i].write_reg(self.ram.read_byte(self.memory_address(location, i)?));
+                }
+                if self.quirks.load_store{
+                    self.index.write_reg(location.wrapping_add(x as u16 + 1));
                 }
             }
-            _ => {
-               println!("Missing Opcode {:04X}", instruction);
+            LdRVx{x} => {
+                let x = x as usize;
+                for i in 0..(x+1){
+                    self.rpl_flags[i] = self.v[i].read_reg();
+                }
             }
+            LdVxR{x} => {
+                let x = x as usize;
+                for i in 0..(x+1){
+                    self.v[i].write_reg(self.rpl_flags[i]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //address of the byte offset bytes past base, which must still be inside the memory the quirks allow
+    fn memory_address(&self, base: u16, offset: usize) -> Result<u16, EmulatorError>{
+        let address = base as usize + offset;
+        let size = if self.quirks.extended_memory {MEMORY_SIZE} else {ORIGINAL_MEMORY_SIZE};
+        if address >= size{
+            return Err(EmulatorError::MemoryOutOfRange{pc: self.current_address, opcode: self.current_opcode, address});
+        }
+        Ok(address as u16)
+    }
+
+    //skips the next instruction, which is two words long if it is F000 NNNN
+    fn skip_instruction(&mut self){
+        if self.ram.read_word(self.program_counter.read_reg()) == 0xF000{
+            self.program_counter.next_instruction();
+        }
+        self.program_counter.next_instruction();
+    }
+
+    //registers VX to VY inclusive, in descending order if X > Y
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>>{
+        if x <= y{
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    pub fn ram(&self) -> &RAM{
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut RAM{
+        &mut self.ram
+    }
+
+    pub fn audio_pattern(&self) -> &[u8; 16]{
+        &self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8{
+        self.pitch
+    }
+
+    pub fn v(&self) -> &[ByteRegister; 16]{
+        &self.v
+    }
+
+    pub fn v_mut(&mut self) -> &mut [ByteRegister; 16]{
+        &mut self.v
+    }
+
+    pub fn index(&self) -> &WordRegister{
+        &self.index
+    }
+
+    pub fn index_mut(&mut self) -> &mut WordRegister{
+        &mut self.index
+    }
+
+    pub fn program_counter(&self) -> &WordRegister{
+        &self.program_counter
+    }
+
+    pub fn program_counter_mut(&mut self) -> &mut WordRegister{
+        &mut self.program_counter
+    }
+
+    //return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[WordRegister]{
+        &self.stack[1..=self.stack_pointer.read_reg() as usize]
+    }
+
+    pub fn stack_pointer(&self) -> &ByteRegister{
+        &self.stack_pointer
+    }
+
+    pub fn rng(&self) -> &dyn RandomSource{
+        self.rng.as_ref()
+    }
+
+    pub fn rng_mut(&mut self) -> &mut dyn RandomSource{
+        self.rng.as_mut()
+    }
+
+    //swaps in another random source, e.g. a seeded or scripted one, and hands back the old one
+    pub fn replace_rng(&mut self, rng: Box<dyn RandomSource>) -> Box<dyn RandomSource>{
+        std::mem::replace(&mut self.rng, rng)
+    }
+
+    pub fn quirks(&self) -> Quirks{
+        self.quirks
+    }
+
+    pub fn delay_timer(&self) -> &ByteRegister{
+        &self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> &ByteRegister{
+        &self.sound_timer
+    }
+
+    pub fn delay_timer_mut(&mut self) -> &mut ByteRegister{
+        &mut self.delay_timer
+    }
+
+    pub fn sound_timer_mut(&mut self) -> &mut ByteRegister{
+        &mut self.sound_timer
+    }
+
+    //quirks first, so a state always resumes with the interpreter it was saved with
+    pub fn save_state(&self, writer: &mut StateWriter){
+        self.quirks.save_state(writer);
+        for reg in self.v.iter(){
+            writer.write_u8(reg.read_reg());
         }
+        writer.write_u16(self.index.read_reg());
+        writer.write_u16(self.program_counter.read_reg());
+        for reg in self.stack[1..].iter(){
+            writer.write_u16(reg.read_reg());
+        }
+        writer.write_u8(self.stack_pointer.read_reg());
+        writer.write_u8(self.delay_timer.read_reg());
+        writer.write_u8(self.sound_timer.read_reg());
+        for pressed in self.keys_pressed{
+            writer.write_bool(pressed);
+        }
+        writer.write_bytes(&self.rpl_flags);
+        writer.write_bytes(&self.audio_pattern);
+        writer.write_u8(self.pitch);
+        writer.write_bool(self.sound_flag);
+        writer.write_bool(self.exit_flag);
+        writer.write_u64(self.rng.state());
+        writer.write_bool(self.vblank);
+        writer.write_bool(self.display_waiting);
+        self.ram.save_state(writer);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<CPU, EmulatorError>{
+        let quirks = Quirks::load_state(reader, reader.version() >= 3, reader.version() >= 4)?;
+        let mut cpu = CPU::new(RAM::new(), quirks);
+        for reg in cpu.v.iter_mut(){
+            reg.write_reg(reader.read_u8()?);
+        }
+        cpu.index.write_reg(reader.read_u16()?);
+        cpu.program_counter.write_reg(reader.read_u16()?);
+        for reg in cpu.stack[1..].iter_mut(){
+            reg.write_reg(reader.read_u16()?);
+        }
+        let stack_pointer = reader.read_u8()?;
+        if stack_pointer as usize >= cpu.stack.len(){
+            return Err(invalid(&format!("stack pointer {} is out of range", stack_pointer)));
+        }
+        cpu.stack_pointer.write_reg(stack_pointer);
+        cpu.delay_timer.write_reg(reader.read_u8()?);
+        cpu.sound_timer.write_reg(reader.read_u8()?);
+        for pressed in cpu.keys_pressed.iter_mut(){
+            *pressed = reader.read_bool()?;
+        }
+        cpu.rpl_flags.copy_from_slice(reader.read_bytes(16)?);
+        cpu.audio_pattern.copy_from_slice(reader.read_bytes(16)?);
+        cpu.pitch = reader.read_u8()?;
+        cpu.sound_flag = reader.read_bool()?;
+        cpu.exit_flag = reader.read_bool()?;
+        //version 1 predates seedable random numbers, so those states keep a fresh source
+        if reader.version() >= 2{
+            cpu.rng.set_state(reader.read_u64()?);
+        }
+        if reader.version() >= 3{
+            cpu.vblank = reader.read_bool()?;
+            cpu.display_waiting = reader.read_bool()?;
+        }
+        cpu.ram = RAM::load_state(reader)?;
+        //the frontend has to redraw and reload the audio pattern
+        cpu.draw_flag = true;
+        cpu.audio_flag = true;
+        Ok(cpu)
+    }
+
+    //called by the frame loop at the end of each frame, releasing a draw stalled by the display wait quirk
+    pub fn vblank(&mut self){
+        self.vblank = true;
+        self.display_waiting = false;
+        self.idle.reset();
+    }
+
+    fn idle_registers(&self) -> Registers{
+        let mut v = [0; 16];
+        for (value, register) in v.iter_mut().zip(self.v.iter()){
+            *value = register.read_reg();
+        }
+        Registers{v, index: self.index.read_reg()}
+    }
+
+    //the busy wait the last instruction went around, if any
+    //every pass until the next timer tick or key change will be the same, so they can be skipped
+    pub fn take_idle_loop(&mut self) -> Option<IdleLoop>{
+        self.idle_loop.take()
+    }
+
+    //for when the keypad changes mid frame, a loop polling it may no longer be idle
+    pub fn reset_idle(&mut self){
+        self.idle.reset();
+        self.idle_loop = None;
+    }
+
+    //true while a draw waits for the next vertical blank
+    pub fn display_waiting(&self) -> bool{
+        self.display_waiting
     }
+
     pub fn timer(&mut self){
         if self.delay_timer.read_reg() > 0{
             self.delay_timer.decrement_reg();
@@ -298,11 +745,13 @@ impl CPU{
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::assemble;
+    use crate::rng::Scripted;
 
     #[test]
     fn timer_test() {
         let mut ram = RAM::new();
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.sound_timer.write_reg(2);
         cpu.delay_timer.write_reg(2);
         cpu.timer();
@@ -320,35 +769,173 @@ mod tests {
     fn cls_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x00E0);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         gfx.toggle(0,0);
         gfx.toggle(0xF,0xF);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(false, gfx.get(0,0));
         assert_eq!(false, gfx.get(0xF,0xF));
         assert_eq!(true, cpu.draw_flag);
     }
 
+    #[test]
     fn ret_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x00EE);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.stack_pointer.write_reg(1);
         cpu.stack[1].write_reg(0xABC);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xABC, cpu.program_counter.read_reg());
         assert_eq!(0, cpu.stack_pointer.read_reg());
     }
 
+    #[test]
+    fn scd_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x00C2);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle(3,1);
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(!gfx.get(3,1));
+        assert!(gfx.get(3,3));
+        assert!(cpu.draw_flag);
+    }
+
+    #[test]
+    fn scr_scl_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x00FB);
+        ram.write_word(0x202, 0x00FC);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle(3,1);
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(gfx.get(7,1));
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(gfx.get(3,1));
+        assert!(!gfx.get(7,1));
+    }
+
+    #[test]
+    fn exit_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x00FD);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(cpu.exit_flag);
+    }
+
+    #[test]
+    fn low_high_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x00FF);
+        ram.write_word(0x202, 0x00FE);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(gfx.is_hires());
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(!gfx.is_hires());
+    }
+
+    #[test]
+    fn ret_empty_stack_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x00EE);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        match cpu.cycle(&mut gfx) {
+            Err(EmulatorError::StackUnderflow{pc, opcode}) => {
+                assert_eq!(0x200, pc);
+                assert_eq!(0x00EE, opcode);
+            }
+            other => panic!("expected a stack underflow, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn call_full_stack_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x2200);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        for _ in 0..16{
+            cpu.cycle(&mut gfx).unwrap();
+        }
+        match cpu.cycle(&mut gfx) {
+            Err(EmulatorError::StackOverflow{pc, ..}) => assert_eq!(0x200, pc),
+            other => panic!("expected a stack overflow, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn invalid_opcode_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x5121);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        match cpu.cycle(&mut gfx) {
+            Err(EmulatorError::InvalidOpcode{pc, opcode}) => {
+                assert_eq!(0x200, pc);
+                assert_eq!(0x5121, opcode);
+            }
+            other => panic!("expected an invalid opcode, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn sys_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x0000);
+        ram.write_word(0x202, 0x0123);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x202, cpu.program_counter.read_reg());
+        match cpu.cycle(&mut gfx) {
+            Err(EmulatorError::InvalidOpcode{pc, opcode}) => {
+                assert_eq!(0x202, pc);
+                assert_eq!(0x0123, opcode);
+            }
+            other => panic!("expected an invalid opcode, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn memory_out_of_range_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF255);
+        let mut cpu = CPU::new(ram, Quirks::xo_chip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0xFFFE);
+        match cpu.cycle(&mut gfx) {
+            Err(EmulatorError::MemoryOutOfRange{address, ..}) => assert_eq!(0x10000, address),
+            other => panic!("expected an out of range access, got {:?}", other)
+        }
+
+        //without XO-CHIP only the first 4 KiB can be reached
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF255);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        cpu.index.write_reg(0xFFE);
+        match cpu.cycle(&mut gfx) {
+            Err(EmulatorError::MemoryOutOfRange{address, ..}) => assert_eq!(0x1000, address),
+            other => panic!("expected an out of range access, got {:?}", other)
+        }
+    }
+
     #[test]
     fn jp_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x1FFF);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xFFF, cpu.program_counter.read_reg());
     }
 
@@ -356,9 +943,9 @@ mod tests {
     fn call_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x2069);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.stack[1].read_reg());
         assert_eq!(0x069, cpu.program_counter.read_reg());
         assert_eq!(1, cpu.stack_pointer.read_reg());
@@ -368,10 +955,10 @@ mod tests {
     fn se_imm_eq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x3781);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[7].write_reg(0x81);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x204, cpu.program_counter.read_reg());
     }
 
@@ -379,10 +966,10 @@ mod tests {
     fn se_imm_neq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x3781);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[7].write_reg(0x80);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.program_counter.read_reg());
     }
 
@@ -390,10 +977,10 @@ mod tests {
     fn sne_eq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x4781);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[7].write_reg(0x81);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.program_counter.read_reg());
     }
 
@@ -401,10 +988,10 @@ mod tests {
     fn sne_neq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x4781);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[7].write_reg(0x80);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x204, cpu.program_counter.read_reg());
     }
 
@@ -412,11 +999,11 @@ mod tests {
     fn se_mem_eq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x55C0);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[5].write_reg(0xA2);
         cpu.v[0xC].write_reg(0xA2);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x204, cpu.program_counter.read_reg());
     }
 
@@ -424,21 +1011,71 @@ mod tests {
     fn se_mem_neq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x55C0);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[5].write_reg(0xA2);
         cpu.v[0xC].write_reg(0xA3);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.program_counter.read_reg());
     }
 
+    #[test]
+    fn se_skip_long_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x3781);
+        ram.write_word(0x202, 0xF000);
+        ram.write_word(0x204, 0x1234);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        cpu.v[7].write_reg(0x81);
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x206, cpu.program_counter.read_reg());
+    }
+
+    #[test]
+    fn save_range_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x5242);
+        ram.write_word(0x202, 0x5422);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[2].write_reg(0xA);
+        cpu.v[3].write_reg(0xB);
+        cpu.v[4].write_reg(0xC);
+        cpu.index.write_reg(0x400);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xA, cpu.ram.read_byte(0x400));
+        assert_eq!(0xC, cpu.ram.read_byte(0x402));
+        cpu.index.write_reg(0x500);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xC, cpu.ram.read_byte(0x500));
+        assert_eq!(0xA, cpu.ram.read_byte(0x502));
+        assert_eq!(0x500, cpu.index.read_reg());
+    }
+
+    #[test]
+    fn load_range_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x5133);
+        ram.write_byte(0x400, 0x11);
+        ram.write_byte(0x401, 0x22);
+        ram.write_byte(0x402, 0x33);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0x400);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x11, cpu.v[1].read_reg());
+        assert_eq!(0x33, cpu.v[3].read_reg());
+        assert_eq!(0, cpu.v[4].read_reg());
+    }
+
     #[test]
     fn ld_imm_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x6A22);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x22, cpu.v[0xA].read_reg());
     }
 
@@ -446,10 +1083,10 @@ mod tests {
     fn add_imm_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x7756);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[7].write_reg(0x37);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x8D, cpu.v[7].read_reg());
     }
 
@@ -457,10 +1094,10 @@ mod tests {
     fn add_imm_overflow_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x77EE);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[7].write_reg(0xEE);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xDC, cpu.v[7].read_reg());
     }
 
@@ -468,10 +1105,10 @@ mod tests {
     fn ld_mem_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8E20);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[2].write_reg(0xD1);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xD1, cpu.v[0xE].read_reg());
     }
 
@@ -479,11 +1116,11 @@ mod tests {
     fn or_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8CE1);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xC].write_reg(0x33);
         cpu.v[0xE].write_reg(0x55);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x77, cpu.v[0xC].read_reg());
     }
 
@@ -491,11 +1128,11 @@ mod tests {
     fn and_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8CE2);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xC].write_reg(0x33);
         cpu.v[0xE].write_reg(0x55);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x11, cpu.v[0xC].read_reg());
     }
 
@@ -503,11 +1140,11 @@ mod tests {
     fn xor_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8CE3);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xC].write_reg(0x33);
         cpu.v[0xE].write_reg(0x55);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x66, cpu.v[0xC].read_reg());
     }
 
@@ -515,11 +1152,11 @@ mod tests {
     fn add_mem_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8BC4);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x45);
         cpu.v[0xC].write_reg(0x67);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xAC, cpu.v[0xB].read_reg());
         assert_eq!(0, cpu.v[0xF].read_reg());
     }
@@ -528,11 +1165,24 @@ mod tests {
     fn add_mem_overflow_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8BC4);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0xEC);
         cpu.v[0xC].write_reg(0x34);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x20, cpu.v[0xB].read_reg());
+        assert_eq!(1, cpu.v[0xF].read_reg());
+    }
+
+    #[test]
+    fn add_mem_assembled_test() {
+        let mut ram = RAM::new();
+        ram.load_bytes(&assemble("LD VB, 0xEC\nLD VC, 0x34\nADD VB, VC").unwrap()).unwrap();
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        for _ in 0..3{
+            cpu.cycle(&mut gfx).unwrap();
+        }
         assert_eq!(0x20, cpu.v[0xB].read_reg());
         assert_eq!(1, cpu.v[0xF].read_reg());
     }
@@ -541,11 +1191,11 @@ mod tests {
     fn sub_no_borrow_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8BC5);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x3A);
         cpu.v[0xC].write_reg(0x24);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x16, cpu.v[0xB].read_reg());
         assert_eq!(1, cpu.v[0xF].read_reg());
     }
@@ -554,11 +1204,11 @@ mod tests {
     fn sub_borrow_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8BC5);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x5C);
         cpu.v[0xC].write_reg(0xA4);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xB8, cpu.v[0xB].read_reg());
         assert_eq!(0, cpu.v[0xF].read_reg());
     }
@@ -567,10 +1217,10 @@ mod tests {
     fn shr_one_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8B06);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x1B);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xD, cpu.v[0xB].read_reg());
         assert_eq!(1, cpu.v[0xF].read_reg());
     }
@@ -579,10 +1229,10 @@ mod tests {
     fn shr_zero_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8B06);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x1C);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xE, cpu.v[0xB].read_reg());
         assert_eq!(0, cpu.v[0xF].read_reg());
     }
@@ -591,11 +1241,11 @@ mod tests {
     fn subn_no_borrow_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8BC7);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x24);
         cpu.v[0xC].write_reg(0x3A);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x16, cpu.v[0xB].read_reg());
         assert_eq!(1, cpu.v[0xF].read_reg());
     }
@@ -604,11 +1254,11 @@ mod tests {
     fn subn_borrow_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8BC7);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0xA4);
         cpu.v[0xC].write_reg(0x5C);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xB8, cpu.v[0xB].read_reg());
         assert_eq!(0, cpu.v[0xF].read_reg());
     }
@@ -617,10 +1267,10 @@ mod tests {
     fn shl_one_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8B0E);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0xE5);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xCA, cpu.v[0xB].read_reg());
         assert_eq!(1, cpu.v[0xF].read_reg());
     }
@@ -629,10 +1279,10 @@ mod tests {
     fn shl_zero_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x8B0E);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0xB].write_reg(0x5E);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xBC, cpu.v[0xB].read_reg());
         assert_eq!(0, cpu.v[0xF].read_reg());
     }
@@ -641,11 +1291,11 @@ mod tests {
     fn sne_mem_eq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x95C0);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[5].write_reg(0xA2);
         cpu.v[0xC].write_reg(0xA2);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.program_counter.read_reg());
     }
 
@@ -653,11 +1303,11 @@ mod tests {
     fn sne_mem_neq_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0x95C0);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         cpu.v[5].write_reg(0xA2);
         cpu.v[0xC].write_reg(0xA3);
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x204, cpu.program_counter.read_reg());
     }
 
@@ -665,9 +1315,9 @@ mod tests {
     fn ld_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xACCC);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xCCC, cpu.index.read_reg());
     }
 
@@ -675,23 +1325,162 @@ mod tests {
     fn jp_mem_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xBCCC);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[0].write_reg(0x11);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xCDD, cpu.program_counter.read_reg());
     }
+
+    #[test]
+    fn jp_mem_jump_quirk_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xBCCC);
+        let mut cpu = CPU::new(ram, Quirks::schip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[0].write_reg(0x11);
+        cpu.v[0xC].write_reg(0x22);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xCEE, cpu.program_counter.read_reg());
+    }
+
+    #[test]
+    fn display_wait_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xD005);
+        ram.write_word(0x202, 0xD005);
+        let mut cpu = CPU::new(ram, Quirks::vip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(cpu.display_waiting());
+        assert_eq!(0x200, cpu.program_counter.read_reg());
+        assert!(!cpu.draw_flag);
+        cpu.vblank();
+        assert!(!cpu.display_waiting());
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(cpu.draw_flag);
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(cpu.display_waiting());
+        assert_eq!(0x202, cpu.program_counter.read_reg());
+    }
+
+    #[test]
+    fn outcome_cycles_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x3005);
+        ram.write_word(0x202, 0x3000);
+        ram.write_word(0x206, 0xF01E);
+        ram.write_word(0x208, 0xF01E);
+        let mut cpu = CPU::new(ram, Quirks::vip());
+        let mut gfx = GraphicsBuffer::new();
+        //a skip costs more when it jumps
+        assert_eq!(50, cpu.cycle(&mut gfx).unwrap());
+        assert_eq!(54, cpu.cycle(&mut gfx).unwrap());
+        //and FX1E when I moves onto the next page
+        cpu.index.write_reg(0x2F0);
+        cpu.v[0].write_reg(0x08);
+        assert_eq!(52, cpu.cycle(&mut gfx).unwrap());
+        assert_eq!(56, cpu.cycle(&mut gfx).unwrap());
+    }
+
+    #[test]
+    fn rnd_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xC30F);
+        ram.write_word(0x202, 0xC3FF);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.replace_rng(Box::new(Scripted::new(vec![0xAB, 0xCD])));
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x0B, cpu.v[3].read_reg());
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xCD, cpu.v[3].read_reg());
+    }
+
+    #[test]
+    fn shr_vy_quirk_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x8BC6);
+        let mut cpu = CPU::new(ram, Quirks::vip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[0xB].write_reg(0x1C);
+        cpu.v[0xC].write_reg(0x1B);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xD, cpu.v[0xB].read_reg());
+        assert_eq!(1, cpu.v[0xF].read_reg());
+    }
+
+    #[test]
+    fn vf_reset_quirk_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0x8CE1);
+        let mut cpu = CPU::new(ram, Quirks::vip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[0xF].write_reg(1);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0, cpu.v[0xF].read_reg());
+    }
+
+    #[test]
+    fn drw_wrap_quirk_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xD011);
+        ram.write_byte(0x400, 0xFF);
+        let mut cpu = CPU::new(ram, Quirks::xo_chip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0x400);
+        cpu.v[0].write_reg(60);
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(gfx.get(63, 0));
+        assert!(gfx.get(0, 0));
+        assert!(gfx.get(3, 0));
+        assert!(!gfx.get(4, 0));
+    }
+
+    #[test]
+    fn drw_clip_quirk_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xD011);
+        ram.write_byte(0x400, 0xFF);
+        let mut cpu = CPU::new(ram, Quirks::schip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0x400);
+        cpu.v[0].write_reg(60);
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(gfx.get(63, 0));
+        assert!(!gfx.get(0, 0));
+    }
     //TODO: DRW test
 
+    #[test]
+    fn drw_big_sprite_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xD120);
+        ram.write_word(0x400, 0x8001);
+        ram.write_word(0x41E, 0xFFFF);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        gfx.set_hires(true);
+        cpu.index.write_reg(0x400);
+        cpu.v[1].write_reg(100);
+        cpu.v[2].write_reg(40);
+        cpu.cycle(&mut gfx).unwrap();
+        assert!(gfx.get(100, 40));
+        assert!(!gfx.get(101, 40));
+        assert!(gfx.get(115, 40));
+        assert!(gfx.get(108, 55));
+        assert_eq!(0, cpu.v[0xF].read_reg());
+    }
+
     #[test]
     fn skp_pressed_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xE39E);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA);
         cpu.keys_pressed[0xA] = true;
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x204, cpu.program_counter.read_reg());
     }
 
@@ -699,10 +1488,10 @@ mod tests {
     fn skp_not_pressed_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xE39E);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.program_counter.read_reg());
     }
 
@@ -710,11 +1499,11 @@ mod tests {
     fn sknp_pressed_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xE3A1);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA);
         cpu.keys_pressed[0xA] = true;
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x202, cpu.program_counter.read_reg());
     }
 
@@ -722,22 +1511,97 @@ mod tests {
     fn sknp_not_pressed_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xE3A1);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x204, cpu.program_counter.read_reg());
+    }
+
+    #[test]
+    fn skp_masked_key_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xE39E);
+        ram.write_word(0x204, 0xE3A1);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[3].write_reg(0x20);
+        cpu.keys_pressed[0] = true;
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x204, cpu.program_counter.read_reg());
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x206, cpu.program_counter.read_reg());
+    }
+
+    #[test]
+    fn wait_key_end_of_memory_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0xFFFE, 0xF00A);
+        let mut cpu = CPU::new(ram, Quirks::xo_chip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.program_counter.write_reg(0xFFFE);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xFFFE, cpu.program_counter.read_reg());
+    }
+
+
+    #[test]
+    fn ld_long_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF000);
+        ram.write_word(0x202, 0xE123);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0xE123, cpu.index.read_reg());
+        assert_eq!(0x204, cpu.program_counter.read_reg());
+    }
+
+    #[test]
+    fn plane_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF301);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(3, gfx.selected_planes());
     }
 
+    #[test]
+    fn audio_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF002);
+        for i in 0..16{
+            ram.write_byte(0x400 + i, i as u8);
+        }
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0x400);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x0F, cpu.audio_pattern()[15]);
+        assert!(cpu.audio_flag);
+    }
+
+    #[test]
+    fn pitch_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF33A);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[3].write_reg(0x70);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x70, cpu.pitch());
+        assert!(cpu.audio_flag);
+    }
 
     #[test]
     fn ld_from_dt_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xF307);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.delay_timer.write_reg(0xCC);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xCC, cpu.v[3].read_reg());
     }
     //TODO: LD key test
@@ -746,10 +1610,10 @@ mod tests {
     fn ld_into_dt_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xF315);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xCC);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xCC, cpu.delay_timer.read_reg());
     }
 
@@ -757,10 +1621,10 @@ mod tests {
     fn ld_into_st_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xF318);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xCC);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xCC, cpu.sound_timer.read_reg());
     }
 
@@ -768,11 +1632,11 @@ mod tests {
     fn add_index_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xF31E);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.index.write_reg(0x2211);
         cpu.v[3].write_reg(0xCC);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x22DD, cpu.index.read_reg());
     }
 
@@ -780,22 +1644,68 @@ mod tests {
     fn ld_sprite_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xF329);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0x82, cpu.index.read_reg());
     }
 
+    #[test]
+    fn drw_both_planes_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF301);
+        ram.write_word(0x202, 0xD011);
+        ram.write_byte(0x400, 0xC0);
+        ram.write_byte(0x401, 0x60);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0x400);
+        cpu.cycle(&mut gfx).unwrap();
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(1, gfx.colour(0, 0));
+        assert_eq!(3, gfx.colour(1, 0));
+        assert_eq!(2, gfx.colour(2, 0));
+        assert_eq!(0, cpu.v[0xF].read_reg());
+    }
+
+    #[test]
+    fn drw_second_plane_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF201);
+        ram.write_word(0x202, 0xD011);
+        ram.write_byte(0x400, 0x80);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle(0, 0);
+        cpu.index.write_reg(0x400);
+        cpu.cycle(&mut gfx).unwrap();
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(3, gfx.colour(0, 0));
+        assert_eq!(0, cpu.v[0xF].read_reg());
+    }
+
+    #[test]
+    fn ld_big_sprite_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF330);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[3].write_reg(0xA);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x104, cpu.index.read_reg());
+        assert_eq!(0x7E, cpu.ram.read_byte(0x104));
+    }
+
     #[test]
     fn bin_dec_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xF333);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA2);
         cpu.index.write_reg(0x400);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(1, cpu.ram.read_byte(0x400));
         assert_eq!(6, cpu.ram.read_byte(0x401));
         assert_eq!(2, cpu.ram.read_byte(0x402));
@@ -805,12 +1715,12 @@ mod tests {
     fn ld_from_reg_test() {
         let mut ram = RAM::new();
         ram.write_word(0x200, 0xFF55);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.v[3].write_reg(0xA2);
         cpu.v[7].write_reg(0x13);
         cpu.index.write_reg(0x400);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xA2, cpu.ram.read_byte(0x403));
         assert_eq!(0x13, cpu.ram.read_byte(0x407));
     }
@@ -821,11 +1731,39 @@ mod tests {
         ram.write_word(0x200, 0xFF65);
         ram.write_byte(0x403, 0xA2);
         ram.write_byte(0x407, 0x13);
-        let mut cpu = CPU::new(ram);
+        let mut cpu = CPU::new(ram, Quirks::default());
         let mut gfx = GraphicsBuffer::new();
         cpu.index.write_reg(0x400);
-        cpu.cycle(&mut gfx);
+        cpu.cycle(&mut gfx).unwrap();
         assert_eq!(0xA2, cpu.v[3].read_reg());
         assert_eq!(0x13, cpu.v[7].read_reg());
     }
+
+    #[test]
+    fn rpl_flags_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF275);
+        ram.write_word(0x202, 0xF285);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.v[0].write_reg(0x12);
+        cpu.v[2].write_reg(0x34);
+        cpu.cycle(&mut gfx).unwrap();
+        cpu.v[0].write_reg(0);
+        cpu.v[2].write_reg(0);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x12, cpu.v[0].read_reg());
+        assert_eq!(0x34, cpu.v[2].read_reg());
+    }
+
+    #[test]
+    fn ld_to_reg_load_store_quirk_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0x200, 0xF365);
+        let mut cpu = CPU::new(ram, Quirks::vip());
+        let mut gfx = GraphicsBuffer::new();
+        cpu.index.write_reg(0x400);
+        cpu.cycle(&mut gfx).unwrap();
+        assert_eq!(0x404, cpu.index.read_reg());
+    }
 }
\ No newline at end of file
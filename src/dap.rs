@@ -0,0 +1,784 @@
+use crate::assembler::{parse_number, SourceMap};
+use crate::breakpoints::Breakpoints;
+use crate::expression::Expression;
+use crate::instruction::Instruction;
+use crate::json::Json;
+use crate::machine::Machine;
+use crate::quirks::Quirks;
+use crate::remote::RemoteDebugger;
+use std::fs::{read, read_to_string};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+//CHIP-8 has a single thread of execution
+const THREAD_ID: i64 = 1;
+
+//variablesReference values of the scopes shown for every frame
+const REGISTERS_SCOPE: i64 = 1;
+const TIMERS_SCOPE: i64 = 2;
+const STACK_SCOPE: i64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    //stopped, or waiting for configurationDone after launch
+    Paused,
+    Running,
+    //a single instruction from the address
+    StepIn{from: u16},
+    //running until a call at the given stack depth returns to the address after it
+    StepOver{return_address: u16, depth: u8},
+    StepOut{depth: u8},
+    Finished
+}
+
+//which requests set a breakpoint, each kind of request replaces only its own
+#[derive(Clone, Copy, Debug, Default)]
+struct Origin {
+    line: bool,
+    instruction: bool
+}
+
+//serves the debug adapter protocol, checked by the frontend before every instruction
+pub struct DapServer {
+    //requests parsed by a reader thread, so the machine can run while waiting for input
+    requests: Receiver<Json>,
+    output: Box<dyn Write + Send>,
+    seq: i64,
+    breakpoints: Breakpoints<Origin>,
+    symbols: Option<SourceMap>,
+    stop_on_entry: bool,
+    state: State,
+    disconnected: bool
+}
+
+impl DapServer {
+    pub fn new<R: Read + Send + 'static, W: Write + Send + 'static>(reader: R, writer: W) -> DapServer {
+        let (sender, requests) = channel();
+        thread::spawn(move || read_messages(reader, sender));
+        DapServer {
+            requests,
+            output: Box::new(writer),
+            seq: 1,
+            breakpoints: Breakpoints::new(),
+            symbols: None,
+            stop_on_entry: false,
+            state: State::Paused,
+            disconnected: false
+        }
+    }
+
+    //talks to an editor that started the emulator as a child process
+    pub fn stdio() -> DapServer {
+        DapServer::new(io::stdin(), io::stdout())
+    }
+
+    //waits for an editor to connect to 127.0.0.1:port
+    pub fn listen(port: u16) -> io::Result<DapServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        DapServer::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<DapServer> {
+        let (stream, _) = listener.accept()?;
+        Ok(DapServer::new(stream.try_clone()?, stream))
+    }
+
+    //true once the editor has disconnected or terminated the session
+    pub fn disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    //answers requests until one launches a ROM, None if the editor disconnects first
+    pub fn launch(&mut self, quirks: Quirks) -> io::Result<Option<Machine>> {
+        while let Ok(request) = self.requests.recv() {
+            match command(&request) {
+                "initialize" => {
+                    let capabilities = Json::object(vec![
+                        ("supportsConfigurationDoneRequest", Json::from(true)),
+                        ("supportsInstructionBreakpoints", Json::from(true)),
+                        ("supportsEvaluateForHovers", Json::from(true)),
+                        ("supportsTerminateRequest", Json::from(true))
+                    ]);
+                    self.respond(&request, capabilities)?;
+                }
+                "launch" => {
+                    let arguments = request.get("arguments").cloned().unwrap_or(Json::Null);
+                    match self.load(&arguments, quirks) {
+                        Ok(machine) => {
+                            self.respond(&request, Json::Null)?;
+                            //breakpoints and configurationDone follow the initialized event
+                            self.event("initialized", Json::Null)?;
+                            return Ok(Some(machine));
+                        }
+                        Err(message) => self.respond_error(&request, &message)?
+                    }
+                }
+                "disconnect" => {
+                    self.respond(&request, Json::Null)?;
+                    break;
+                }
+                other => self.respond_error(&request, &format!("'{}' is not available before launch", other))?
+            }
+        }
+        self.disconnected = true;
+        Ok(None)
+    }
+
+    //reads the ROM and source map named by the launch arguments
+    fn load(&mut self, arguments: &Json, quirks: Quirks) -> Result<Machine, String> {
+        let program = arguments.get("program").and_then(Json::as_str).ok_or("launch needs a 'program' path")?;
+        let quirks = match arguments.get("quirks").and_then(Json::as_str) {
+            Some(name) => Quirks::from_name(name).ok_or(format!("Unknown quirks profile '{}'", name))?,
+            None => quirks
+        };
+        let rom = read(program).map_err(|error| format!("Could not read {}: {}", program, error))?;
+        let mut machine = Machine::new(quirks);
+        machine.load_rom(&rom).map_err(|error| format!("Could not load {}: {}", program, error))?;
+        //an explicit source map must exist, the default one next to the ROM is optional
+        self.symbols = match arguments.get("symbols").and_then(Json::as_str) {
+            Some(path) => Some(load_symbols(path)?),
+            None => {
+                let path = Path::new(program).with_extension("map");
+                if path.exists() {Some(load_symbols(&path.to_string_lossy())?)} else {None}
+            }
+        };
+        self.stop_on_entry = arguments.get("stopOnEntry").and_then(Json::as_bool).unwrap_or(false);
+        Ok(machine)
+    }
+
+    //called before every instruction, blocks answering requests while the ROM is stopped
+    pub fn check(&mut self, machine: &mut Machine) -> io::Result<()> {
+        if self.state == State::Finished {
+            return Ok(());
+        }
+        if self.state != State::Paused {
+            self.poll(machine)?;
+        }
+        let pc = machine.cpu().program_counter().read_reg();
+        let depth = machine.cpu().stack_pointer().read_reg();
+        let breakpoint = self.breakpoints.hit(pc, |_| true);
+        let stepped = match self.state {
+            //a draw waiting for the vertical blank has not run yet, so the step goes on until it does
+            State::StepIn{from} => !machine.stalled_draw(from),
+            State::StepOver{return_address, depth: call_depth} => pc == return_address && depth == call_depth,
+            State::StepOut{depth: call_depth} => depth < call_depth,
+            _ => false
+        };
+        if self.state != State::Paused && self.state != State::Finished {
+            if breakpoint {
+                self.stop("breakpoint")?;
+            } else if stepped {
+                self.stop("step")?;
+            }
+        }
+        while self.state == State::Paused {
+            match self.requests.recv() {
+                Ok(request) => self.handle(&request, machine)?,
+                Err(_) => self.finish()
+            }
+        }
+        Ok(())
+    }
+
+    //answers requests that arrived while the ROM was running
+    fn poll(&mut self, machine: &mut Machine) -> io::Result<()> {
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => self.handle(&request, machine)?,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    self.finish();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    //tells the editor the ROM has finished
+    pub fn report_exit(&mut self, code: i64) -> io::Result<()> {
+        if self.state == State::Finished {
+            return Ok(());
+        }
+        self.state = State::Finished;
+        self.event("exited", Json::object(vec![("exitCode", Json::from(code))]))?;
+        self.event("terminated", Json::Null)
+    }
+
+    //shows a message in the editor's debug console
+    pub fn output(&mut self, message: &str) -> io::Result<()> {
+        self.event("output", Json::object(vec![
+            ("category", Json::from("console")),
+            ("output", Json::from(format!("{}\n", message)))
+        ]))
+    }
+
+    fn finish(&mut self) {
+        self.state = State::Finished;
+        self.disconnected = true;
+    }
+
+    fn stop(&mut self, reason: &str) -> io::Result<()> {
+        self.state = State::Paused;
+        self.event("stopped", Json::object(vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true))
+        ]))
+    }
+
+    fn resume(&mut self, machine: &Machine, state: State) {
+        self.state = state;
+        self.breakpoints.resume(machine.cpu().program_counter().read_reg());
+    }
+
+    fn handle(&mut self, request: &Json, machine: &mut Machine) -> io::Result<()> {
+        let arguments = request.get("arguments").cloned().unwrap_or(Json::Null);
+        let body = match command(request) {
+            "configurationDone" => {
+                self.respond(request, Json::Null)?;
+                if self.stop_on_entry {
+                    return self.stop("entry");
+                }
+                self.resume(machine, State::Running);
+                return Ok(());
+            }
+            "threads" => Ok(Json::object(vec![
+                ("threads", Json::from(vec![Json::object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("CHIP-8"))
+                ])]))
+            ])),
+            "setBreakpoints" => Ok(self.set_breakpoints(&arguments)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(&arguments)),
+            "setExceptionBreakpoints" => Ok(Json::object(vec![("breakpoints", Json::from(Vec::<Json>::new()))])),
+            "stackTrace" => Ok(self.stack_trace(machine)),
+            "scopes" => Ok(scopes()),
+            "variables" => Ok(variables(machine, arguments.get("variablesReference").and_then(Json::as_i64))),
+            "evaluate" => evaluate(machine, &arguments),
+            "continue" => {
+                self.resume(machine, State::Running);
+                Ok(Json::object(vec![("allThreadsContinued", Json::from(true))]))
+            }
+            "stepIn" => {
+                let from = machine.cpu().program_counter().read_reg();
+                self.resume(machine, State::StepIn{from});
+                Ok(Json::Null)
+            }
+            //steps over calls by running until they return
+            "next" => {
+                let cpu = machine.cpu();
+                let pc = cpu.program_counter().read_reg();
+                let state = match Instruction::decode(cpu.ram().read_word(pc)) {
+                    Some(Instruction::Call(_)) => State::StepOver{
+                        return_address: pc.wrapping_add(2),
+                        depth: cpu.stack_pointer().read_reg()
+                    },
+                    _ => State::StepIn{from: pc}
+                };
+                self.resume(machine, state);
+                Ok(Json::Null)
+            }
+            "stepOut" => {
+                let depth = machine.cpu().stack_pointer().read_reg();
+                self.resume(machine, State::StepOut{depth});
+                Ok(Json::Null)
+            }
+            "pause" => {
+                self.respond(request, Json::Null)?;
+                if self.state == State::Paused {
+                    return Ok(());
+                }
+                return self.stop("pause");
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Json::Null)?;
+                if command(request) == "terminate" {
+                    self.event("terminated", Json::Null)?;
+                }
+                self.finish();
+                return Ok(());
+            }
+            other => Err(format!("Unsupported request '{}'", other))
+        };
+        match body {
+            Ok(body) => self.respond(request, body),
+            Err(message) => self.respond_error(request, &message)
+        }
+    }
+
+    //line breakpoints replace every earlier one in the ROM's source file
+    //editors send every open file's breakpoints, and those in other files can never be hit
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        let requested = arguments.get("breakpoints").and_then(Json::as_array).unwrap_or(&[]);
+        let path = arguments.get("source").and_then(|source| source.get("path")).and_then(Json::as_str);
+        let rom_source = self.symbols.as_ref().and_then(|symbols| symbols.source());
+        if let (Some(path), Some(rom_source)) = (path, rom_source) {
+            if path != rom_source {
+                let breakpoints: Vec<Json> = requested.iter().map(|breakpoint| Json::object(vec![
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(breakpoint.get("line").and_then(Json::as_i64).unwrap_or(0))),
+                    ("message", Json::from("Not the ROM's source file"))
+                ])).collect();
+                return Json::object(vec![("breakpoints", Json::from(breakpoints))]);
+            }
+        }
+        self.breakpoints.retain(|origin| {
+            origin.line = false;
+            origin.instruction
+        });
+        let breakpoints: Vec<Json> = requested.iter().map(|breakpoint| {
+            let line = breakpoint.get("line").and_then(Json::as_i64).unwrap_or(0);
+            let address = self.symbols.as_ref().and_then(|symbols| symbols.address_of_line(line as usize));
+            match address {
+                Some(address) => {
+                    self.breakpoints.entry(address).line = true;
+                    Json::object(vec![
+                        ("verified", Json::from(true)),
+                        ("line", Json::from(line)),
+                        ("instructionReference", Json::from(format!("0x{:03X}", address)))
+                    ])
+                }
+                None => {
+                    let message = if self.symbols.is_some() {"No instruction on this line"} else {"No source map for this ROM"};
+                    Json::object(vec![
+                        ("verified", Json::from(false)),
+                        ("line", Json::from(line)),
+                        ("message", Json::from(message))
+                    ])
+                }
+            }
+        }).collect();
+        Json::object(vec![("breakpoints", Json::from(breakpoints))])
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Json) -> Json {
+        self.breakpoints.retain(|origin| {
+            origin.instruction = false;
+            origin.line
+        });
+        let requested = arguments.get("breakpoints").and_then(Json::as_array).unwrap_or(&[]);
+        let breakpoints: Vec<Json> = requested.iter().map(|breakpoint| {
+            let reference = breakpoint.get("instructionReference").and_then(Json::as_str).unwrap_or("");
+            let offset = breakpoint.get("offset").and_then(Json::as_i64).unwrap_or(0);
+            let address = parse_number(reference)
+                .map(|address| address as i64 + offset)
+                .filter(|address| (0..=0xFFFF).contains(address));
+            match address {
+                Some(address) => {
+                    self.breakpoints.entry(address as u16).instruction = true;
+                    Json::object(vec![
+                        ("verified", Json::from(true)),
+                        ("instructionReference", Json::from(format!("0x{:03X}", address)))
+                    ])
+                }
+                None => Json::object(vec![
+                    ("verified", Json::from(false)),
+                    ("message", Json::from(format!("Invalid address '{}'", reference)))
+                ])
+            }
+        }).collect();
+        Json::object(vec![("breakpoints", Json::from(breakpoints))])
+    }
+
+    //the current instruction, then the call that led to each return address on the stack
+    fn stack_trace(&self, machine: &Machine) -> Json {
+        let cpu = machine.cpu();
+        let mut addresses = vec![cpu.program_counter().read_reg()];
+        addresses.extend(cpu.stack().iter().rev().map(|reg| reg.read_reg().wrapping_sub(2)));
+        let frames: Vec<Json> = addresses.iter().enumerate().map(|(id, address)| {
+            let symbols = self.symbols.as_ref();
+            let name = symbols.and_then(|symbols| symbols.label_before(*address))
+                .map(|(label, start)| if start == *address {label.to_string()} else {format!("{}+{}", label, address - start)})
+                .unwrap_or(format!("0x{:03X}", address));
+            let line = symbols.and_then(|symbols| symbols.line_of_address(*address));
+            let mut frame = vec![
+                ("id", Json::from(id as i64)),
+                ("name", Json::from(name)),
+                ("line", Json::from(line.unwrap_or(0) as i64)),
+                ("column", Json::from(if line.is_some() {1} else {0})),
+                ("instructionPointerReference", Json::from(format!("0x{:03X}", address)))
+            ];
+            if let (Some(path), Some(_)) = (symbols.and_then(SourceMap::source), line) {
+                let name = Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                frame.push(("source", Json::object(vec![("name", Json::from(name)), ("path", Json::from(path))])));
+            }
+            Json::object(frame)
+        }).collect();
+        let total = frames.len() as i64;
+        Json::object(vec![("stackFrames", Json::from(frames)), ("totalFrames", Json::from(total))])
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        self.send(vec![
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("success", Json::from(true)),
+            ("command", Json::from(command(request))),
+            ("body", body)
+        ])
+    }
+
+    fn respond_error(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        self.send(vec![
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("success", Json::from(false)),
+            ("command", Json::from(command(request))),
+            ("message", Json::from(message))
+        ])
+    }
+
+    fn event(&mut self, name: &str, body: Json) -> io::Result<()> {
+        self.send(vec![("type", Json::from("event")), ("event", Json::from(name)), ("body", body)])
+    }
+
+    //every message is JSON preceded by a Content-Length header
+    fn send(&mut self, mut fields: Vec<(&str, Json)>) -> io::Result<()> {
+        fields.push(("seq", Json::from(self.seq)));
+        self.seq += 1;
+        let body = Json::object(fields).to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+impl RemoteDebugger for DapServer {
+    fn check(&mut self, machine: &mut Machine) -> io::Result<()> {
+        DapServer::check(self, machine)
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.disconnected
+    }
+
+    fn report_error(&mut self, message: &str) -> io::Result<()> {
+        self.output(message)?;
+        self.report_exit(1)
+    }
+
+    fn client_name(&self) -> &'static str {
+        "the debug adapter client"
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn load_symbols(path: &str) -> Result<SourceMap, String> {
+    let text = read_to_string(path).map_err(|error| format!("Could not read {}: {}", path, error))?;
+    SourceMap::parse(&text).map_err(|error| format!("{}: {}", path, error))
+}
+
+//runs on its own thread until the editor closes the connection
+fn read_messages<R: Read>(reader: R, sender: Sender<Json>) {
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0; length.unwrap_or(0)];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        //malformed messages are dropped, there is no request to reply to
+        if let Ok(request) = Json::parse(&String::from_utf8_lossy(&body)) {
+            if sender.send(request).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64| Json::object(vec![
+        ("name", Json::from(name)),
+        ("variablesReference", Json::from(reference)),
+        ("expensive", Json::from(false))
+    ]);
+    Json::object(vec![("scopes", Json::from(vec![
+        scope("Registers", REGISTERS_SCOPE),
+        scope("Timers", TIMERS_SCOPE),
+        scope("Stack", STACK_SCOPE)
+    ]))])
+}
+
+fn variables(machine: &Machine, reference: Option<i64>) -> Json {
+    let cpu = machine.cpu();
+    let variable = |name: &str, value: String| Json::object(vec![
+        ("name", Json::from(name)),
+        ("value", Json::from(value)),
+        ("variablesReference", Json::from(0))
+    ]);
+    let variables: Vec<Json> = match reference {
+        Some(REGISTERS_SCOPE) => {
+            let mut variables: Vec<Json> = cpu.v().iter()
+                .map(|reg| variable(reg.name(), format!("0x{:02X}", reg.read_reg())))
+                .collect();
+            for reg in [cpu.index(), cpu.program_counter()].iter() {
+                variables.push(variable(reg.name(), format!("0x{:03X}", reg.read_reg())));
+            }
+            variables.push(variable(cpu.stack_pointer().name(), cpu.stack_pointer().read_reg().to_string()));
+            variables
+        }
+        Some(TIMERS_SCOPE) => [cpu.delay_timer(), cpu.sound_timer()].iter()
+            .map(|reg| variable(reg.name(), reg.read_reg().to_string()))
+            .collect(),
+        Some(STACK_SCOPE) => cpu.stack().iter()
+            .map(|reg| variable(reg.name(), format!("0x{:03X}", reg.read_reg())))
+            .collect(),
+        _ => Vec::new()
+    };
+    Json::object(vec![("variables", Json::from(variables))])
+}
+
+//watch and hover expressions use the same language as the command line debugger
+fn evaluate(machine: &Machine, arguments: &Json) -> Result<Json, String> {
+    let text = arguments.get("expression").and_then(Json::as_str).unwrap_or("");
+    let value = Expression::parse(text)?.evaluate(machine.cpu());
+    Ok(Json::object(vec![
+        ("result", Json::from(format!("{} (0x{:X})", value, value))),
+        ("variablesReference", Json::from(0))
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_with_map;
+    use std::collections::VecDeque;
+    use std::env::temp_dir;
+    use std::fs::write;
+    use std::net::TcpStream;
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        seq: i64,
+        //events that arrived while waiting for a response
+        events: VecDeque<Json>
+    }
+
+    impl Client {
+        fn receive(&mut self) -> Json {
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                self.reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                length = header.trim().trim_start_matches("Content-Length:").trim().parse().unwrap();
+            }
+            let mut body = vec![0; length];
+            self.reader.read_exact(&mut body).unwrap();
+            Json::parse(&String::from_utf8(body).unwrap()).unwrap()
+        }
+
+        fn request(&mut self, command: &str, arguments: Json) -> Json {
+            let seq = self.seq;
+            self.seq += 1;
+            let body = Json::object(vec![
+                ("seq", Json::from(seq)),
+                ("type", Json::from("request")),
+                ("command", Json::from(command)),
+                ("arguments", arguments)
+            ]).to_string();
+            write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+            loop {
+                let message = self.receive();
+                if message.get("type").and_then(Json::as_str) == Some("event") {
+                    self.events.push_back(message);
+                } else {
+                    assert_eq!(Some(seq), message.get("request_seq").and_then(Json::as_i64));
+                    return message;
+                }
+            }
+        }
+
+        fn event(&mut self, name: &str) -> Json {
+            loop {
+                let message = match self.events.pop_front() {
+                    Some(message) => message,
+                    None => self.receive()
+                };
+                if message.get("event").and_then(Json::as_str) == Some(name) {
+                    return message;
+                }
+            }
+        }
+    }
+
+    //assembles a ROM with a source map into the temp directory and starts a session on it
+    fn launch(name: &str, source: &str, stop_on_entry: bool) -> (Client, thread::JoinHandle<Machine>) {
+        let (rom, mut map) = assemble_with_map(source).unwrap();
+        map.set_source(String::from("/roms/test.asm"));
+        let rom_path = temp_dir().join(format!("ferric-8-dap-{}.ch8", name));
+        write(&rom_path, rom).unwrap();
+        write(rom_path.with_extension("map"), map.to_string()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = DapServer::accept(&listener).unwrap();
+        let handle = thread::spawn(move || {
+            let mut machine = server.launch(Quirks::default()).unwrap().unwrap();
+            loop {
+                server.check(&mut machine).unwrap();
+                if server.disconnected() {
+                    break;
+                }
+                machine.step().unwrap();
+                if machine.exited() {
+                    server.report_exit(0).unwrap();
+                    break;
+                }
+            }
+            machine
+        });
+        let mut client = Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream, seq: 1, events: VecDeque::new() };
+        let initialize = client.request("initialize", Json::object(vec![("adapterID", Json::from("ferric-8"))]));
+        assert_eq!(Some(true), initialize.get("body").and_then(|body| body.get("supportsConfigurationDoneRequest")).and_then(Json::as_bool));
+        let launch = client.request("launch", Json::object(vec![
+            ("program", Json::from(rom_path.to_string_lossy().into_owned())),
+            ("stopOnEntry", Json::from(stop_on_entry))
+        ]));
+        assert_eq!(Some(true), launch.get("success").and_then(Json::as_bool));
+        client.event("initialized");
+        (client, handle)
+    }
+
+    fn body_field<'a>(response: &'a Json, field: &str) -> &'a [Json] {
+        response.get("body").and_then(|body| body.get(field)).and_then(Json::as_array).unwrap()
+    }
+
+    fn string_field<'a>(json: &'a Json, field: &str) -> &'a str {
+        json.get(field).and_then(Json::as_str).unwrap()
+    }
+
+    const PROGRAM: &str = "\
+start:
+    LD V0, 1
+    CALL add
+    CALL add
+    EXIT
+add:
+    ADD V1, 2
+    RET";
+
+    #[test]
+    fn source_breakpoint_test() {
+        let (mut client, handle) = launch("source", PROGRAM, false);
+        let response = client.request("setBreakpoints", Json::object(vec![
+            ("source", Json::object(vec![("path", Json::from("/roms/test.asm"))])),
+            ("breakpoints", Json::from(vec![
+                Json::object(vec![("line", Json::from(7))]),
+                Json::object(vec![("line", Json::from(6))])
+            ]))
+        ]));
+        let breakpoints = body_field(&response, "breakpoints");
+        assert_eq!(Some(true), breakpoints[0].get("verified").and_then(Json::as_bool));
+        assert_eq!(Some(false), breakpoints[1].get("verified").and_then(Json::as_bool));
+        client.request("configurationDone", Json::Null);
+        assert_eq!("breakpoint", string_field(client.event("stopped").get("body").unwrap(), "reason"));
+
+        let trace = client.request("stackTrace", Json::object(vec![("threadId", Json::from(1))]));
+        let frames = body_field(&trace, "stackFrames");
+        assert_eq!(2, frames.len());
+        assert_eq!("add", string_field(&frames[0], "name"));
+        assert_eq!(Some(7), frames[0].get("line").and_then(Json::as_i64));
+        assert_eq!("/roms/test.asm", string_field(frames[0].get("source").unwrap(), "path"));
+        assert_eq!("start+2", string_field(&frames[1], "name"));
+        assert_eq!(Some(3), frames[1].get("line").and_then(Json::as_i64));
+
+        client.request("continue", Json::Null);
+        client.event("stopped");
+        let registers = client.request("variables", Json::object(vec![("variablesReference", Json::from(REGISTERS_SCOPE))]));
+        let v1 = &body_field(&registers, "variables")[1];
+        assert_eq!(("V1", "0x02"), (string_field(v1, "name"), string_field(v1, "value")));
+        client.request("continue", Json::Null);
+        assert_eq!(Some(0), client.event("exited").get("body").and_then(|body| body.get("exitCode")).and_then(Json::as_i64));
+        assert_eq!(4, handle.join().unwrap().cpu().v()[1].read_reg());
+    }
+
+    #[test]
+    fn other_source_breakpoint_test() {
+        let (mut client, handle) = launch("other-source", PROGRAM, false);
+        let set = |client: &mut Client, path: &str| client.request("setBreakpoints", Json::object(vec![
+            ("source", Json::object(vec![("path", Json::from(path))])),
+            ("breakpoints", Json::from(vec![Json::object(vec![("line", Json::from(7))])]))
+        ]));
+        let rom = set(&mut client, "/roms/test.asm");
+        assert_eq!(Some(true), body_field(&rom, "breakpoints")[0].get("verified").and_then(Json::as_bool));
+        let other = set(&mut client, "/roms/other.asm");
+        assert_eq!(Some(false), body_field(&other, "breakpoints")[0].get("verified").and_then(Json::as_bool));
+        client.request("configurationDone", Json::Null);
+        assert_eq!("breakpoint", string_field(client.event("stopped").get("body").unwrap(), "reason"));
+        client.request("disconnect", Json::Null);
+        assert_eq!(0x208, handle.join().unwrap().cpu().program_counter().read_reg());
+    }
+
+    #[test]
+    fn stepping_test() {
+        let (mut client, handle) = launch("stepping", PROGRAM, true);
+        client.request("configurationDone", Json::Null);
+        assert_eq!("entry", string_field(client.event("stopped").get("body").unwrap(), "reason"));
+        let pc = |client: &mut Client| {
+            let trace = client.request("stackTrace", Json::Null);
+            string_field(&body_field(&trace, "stackFrames")[0], "instructionPointerReference").to_string()
+        };
+        client.request("next", Json::Null);
+        client.event("stopped");
+        assert_eq!("0x202", pc(&mut client));
+        //next runs the whole subroutine
+        client.request("next", Json::Null);
+        client.event("stopped");
+        assert_eq!("0x204", pc(&mut client));
+        client.request("stepIn", Json::Null);
+        client.event("stopped");
+        assert_eq!("0x208", pc(&mut client));
+        client.request("stepOut", Json::Null);
+        client.event("stopped");
+        assert_eq!("0x206", pc(&mut client));
+        let evaluate = client.request("evaluate", Json::object(vec![("expression", Json::from("v1 + v0"))]));
+        assert_eq!("5 (0x5)", string_field(evaluate.get("body").unwrap(), "result"));
+        let stack = client.request("variables", Json::object(vec![("variablesReference", Json::from(STACK_SCOPE))]));
+        assert!(body_field(&stack, "variables").is_empty());
+        client.request("disconnect", Json::Null);
+        assert_eq!(0x206, handle.join().unwrap().cpu().program_counter().read_reg());
+    }
+
+    #[test]
+    fn instruction_breakpoint_and_pause_test() {
+        let (mut client, handle) = launch("pause", "loop: ADD V2, 1\nJP loop", false);
+        let response = client.request("setInstructionBreakpoints", Json::object(vec![
+            ("breakpoints", Json::from(vec![Json::object(vec![("instructionReference", Json::from("0x202"))])]))
+        ]));
+        assert_eq!(Some(true), body_field(&response, "breakpoints")[0].get("verified").and_then(Json::as_bool));
+        client.request("configurationDone", Json::Null);
+        client.event("stopped");
+        client.request("setInstructionBreakpoints", Json::object(vec![("breakpoints", Json::from(Vec::<Json>::new()))]));
+        client.request("continue", Json::Null);
+        client.request("pause", Json::Null);
+        assert_eq!("pause", string_field(client.event("stopped").get("body").unwrap(), "reason"));
+        let failed = client.request("launch", Json::Null);
+        assert_eq!(Some(false), failed.get("success").and_then(Json::as_bool));
+        client.request("terminate", Json::Null);
+        client.event("terminated");
+        assert!(handle.join().unwrap().cpu().v()[2].read_reg() > 1);
+    }
+}
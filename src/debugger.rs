@@ -0,0 +1,604 @@
+use crate::assembler::parse_number;
+use crate::breakpoints::Breakpoints;
+use crate::expression::{Expression, Template};
+use crate::instruction::Instruction;
+use crate::machine::Machine;
+use crate::ram::{Access, WatchHit, Watchpoint};
+use std::collections::BTreeMap;
+
+const DEFAULT_MEMORY_LENGTH: usize = 32;
+const DEFAULT_DISASSEMBLY_LENGTH: usize = 10;
+
+const HELP: &str = "\
+step [n]          execute n instructions, default 1
+continue          run until the next breakpoint
+break <addr> [if <expr>]
+                  stop before executing the instruction at addr, optionally only when expr is non-zero
+trace <addr> \"<message>\"
+                  log a message such as \"score={v5} I={i:x}\" whenever addr is executed
+watch <addr> [len]
+                  stop after an instruction writes to len bytes starting at addr, default 1
+rwatch <addr> [len]
+                  stop after an instruction reads from the range
+awatch <addr> [len]
+                  stop after an instruction reads from or writes to the range
+delete [addr]     remove the breakpoint, tracepoint and watchpoint at addr, or all of them
+print <expr>      evaluate an expression over v0-vf, i, pc, sp, dt, st, key[n] and ram[addr]
+regs              show the registers and timers
+stack             show the return addresses on the stack
+mem <addr> [len]  dump len bytes of memory starting at addr
+set <reg> <val>   write a value to V0-VF, I or PC
+disasm [addr] [n] disassemble n instructions starting at addr, default the program counter
+quit              stop emulation";
+
+//a command line debugger, the frontend feeds it lines and checks it before every instruction
+pub struct Debugger {
+    //breakpoints with their optional condition
+    breakpoints: Breakpoints<Option<Expression>>,
+    tracepoints: BTreeMap<u16, Template>,
+    //tracepoint and watchpoint messages not yet collected by the frontend
+    log: Vec<String>,
+    //address and listing of the instruction being executed, so watchpoint hits can name it
+    executing: Option<(u16, String)>,
+    paused: bool,
+    quit: bool
+}
+
+impl Debugger {
+    //starts paused so the ROM can be inspected before the first instruction
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Breakpoints::new(),
+            tracepoints: BTreeMap::new(),
+            log: Vec::new(),
+            executing: None,
+            paused: true,
+            quit: false
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.addresses()
+    }
+
+    //messages logged by tracepoints and watchpoints since the last call
+    pub fn take_log(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+
+    //called before every instruction, returns true if the machine should not run it
+    pub fn check(&mut self, machine: &Machine) -> bool {
+        if self.report_watch_hits(machine) {
+            self.paused = true;
+        }
+        let pc = machine.cpu().program_counter().read_reg();
+        if self.breakpoints.hit(pc, |condition| triggers(condition, machine)) {
+            self.paused = true;
+        }
+        if !self.paused {
+            self.before_step(machine);
+        }
+        self.paused
+    }
+
+    //logs the tracepoint at the program counter, if any, and remembers the instruction about to run
+    fn before_step(&mut self, machine: &Machine) {
+        let pc = machine.cpu().program_counter().read_reg();
+        if let Some(template) = self.tracepoints.get(&pc) {
+            self.log.push(format!("0x{:03X}: {}", pc, template.render(machine.cpu())));
+        }
+        self.executing = Some((pc, decode(machine, pc).0));
+    }
+
+    //logs the watched accesses made by the last instruction, returns true if there were any
+    fn report_watch_hits(&mut self, machine: &Machine) -> bool {
+        let hits = machine.cpu().ram().take_watch_hits();
+        let (pc, instruction) = match self.executing.take() {
+            Some(executing) => executing,
+            None => return false
+        };
+        for hit in hits.iter() {
+            self.log.push(describe_hit(hit, pc, &instruction));
+        }
+        !hits.is_empty()
+    }
+
+    //the instruction about to be executed, shown before each prompt
+    pub fn location(&self, machine: &Machine) -> String {
+        let pc = machine.cpu().program_counter().read_reg();
+        self.disassemble_line(machine, pc).0
+    }
+
+    //runs one line typed at the prompt and returns the text to print
+    pub fn execute(&mut self, machine: &mut Machine, line: &str) -> String {
+        let mut parts = line.trim().splitn(2, char::is_whitespace);
+        let command = match parts.next() {
+            Some(command) if !command.is_empty() => command,
+            _ => return String::new()
+        };
+        //the unsplit remainder, for commands that take an expression or message
+        let rest = parts.next().unwrap_or("").trim();
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let args = words.as_slice();
+        let result = match command {
+            "step" | "s" => self.step(machine, args),
+            "continue" | "c" => {
+                self.paused = false;
+                self.breakpoints.resume(machine.cpu().program_counter().read_reg());
+                Ok(String::new())
+            }
+            "break" | "b" => self.add_breakpoint(rest),
+            "trace" | "t" => self.add_tracepoint(rest),
+            "watch" | "w" => add_watchpoint(machine, args, false, true),
+            "rwatch" => add_watchpoint(machine, args, true, false),
+            "awatch" => add_watchpoint(machine, args, true, true),
+            "delete" | "d" => self.delete_breakpoint(machine, args),
+            "regs" | "r" => Ok(registers(machine)),
+            "stack" => Ok(stack(machine)),
+            "mem" | "m" => memory(machine, args),
+            "set" => set_register(machine, args),
+            "disasm" => self.disassemble(machine, args),
+            "print" | "p" => Expression::parse(rest).map(|expression| {
+                let value = expression.evaluate(machine.cpu());
+                format!("{} (0x{:X})", value, value)
+            }),
+            "quit" | "q" => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            "help" | "h" => Ok(String::from(HELP)),
+            _ => Err(format!("Unknown command '{}', type help for a list", command))
+        };
+        result.unwrap_or_else(|error| error)
+    }
+
+    fn step(&mut self, machine: &mut Machine, args: &[&str]) -> Result<String, String> {
+        let count = match args.first() {
+            Some(count) => parse_number(count).ok_or(format!("Invalid count '{}'", count))? as usize,
+            None => 1
+        };
+        //the instruction stepped from runs even if it has a breakpoint
+        self.breakpoints.resume(machine.cpu().program_counter().read_reg());
+        for _ in 0..count {
+            let pc = machine.cpu().program_counter().read_reg();
+            if self.breakpoints.hit(pc, |condition| triggers(condition, machine)) {
+                return Ok(format!("Breakpoint at 0x{:03X}", pc));
+            }
+            if machine.exited() {
+                return Err(String::from("The program has exited"));
+            }
+            self.before_step(machine);
+            machine.step().map_err(|error| format!("Emulation stopped: {}", error))?;
+            if machine.stalled_draw(pc) {
+                machine.step().map_err(|error| format!("Emulation stopped: {}", error))?;
+            }
+            if self.report_watch_hits(machine) {
+                break;
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn add_breakpoint(&mut self, rest: &str) -> Result<String, String> {
+        let (address, condition) = split_address(rest)?;
+        if condition.is_empty() {
+            self.breakpoints.insert(address, None);
+            return Ok(format!("Breakpoint set at 0x{:03X}", address));
+        }
+        let source = condition.strip_prefix("if")
+            .filter(|source| source.starts_with(char::is_whitespace))
+            .ok_or("Usage: break <addr> [if <expr>]")?
+            .trim();
+        self.breakpoints.insert(address, Some(Expression::parse(source)?));
+        Ok(format!("Breakpoint set at 0x{:03X} if {}", address, source))
+    }
+
+    fn add_tracepoint(&mut self, rest: &str) -> Result<String, String> {
+        let (address, message) = split_address(rest)?;
+        let message = message.strip_prefix('"')
+            .and_then(|message| message.strip_suffix('"'))
+            .ok_or("Usage: trace <addr> \"<message>\"")?;
+        self.tracepoints.insert(address, Template::parse(message)?);
+        Ok(format!("Tracepoint set at 0x{:03X}", address))
+    }
+
+    fn delete_breakpoint(&mut self, machine: &mut Machine, args: &[&str]) -> Result<String, String> {
+        if args.is_empty() {
+            self.breakpoints.clear();
+            self.tracepoints.clear();
+            machine.cpu_mut().ram_mut().clear_watchpoints();
+            return Ok(String::from("Deleted all breakpoints, tracepoints and watchpoints"));
+        }
+        let address = parse_address(args.first())?;
+        let breakpoint = self.breakpoints.remove(address).is_some();
+        let tracepoint = self.tracepoints.remove(&address).is_some();
+        let watchpoint = machine.cpu_mut().ram_mut().remove_watchpoint(address);
+        if breakpoint || tracepoint || watchpoint {
+            Ok(format!("Deleted 0x{:03X}", address))
+        } else {
+            Err(format!("No breakpoint, tracepoint or watchpoint at 0x{:03X}", address))
+        }
+    }
+
+    fn disassemble(&self, machine: &Machine, args: &[&str]) -> Result<String, String> {
+        let mut address = match args.first() {
+            Some(_) => parse_address(args.first())?,
+            None => machine.cpu().program_counter().read_reg()
+        };
+        let count = match args.get(1) {
+            Some(count) => parse_number(count).ok_or(format!("Invalid count '{}'", count))? as usize,
+            None => DEFAULT_DISASSEMBLY_LENGTH
+        };
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let (line, length) = self.disassemble_line(machine, address);
+            lines.push(line);
+            address = address.wrapping_add(length);
+        }
+        Ok(lines.join("\n"))
+    }
+
+    //one line of listing with markers for the program counter and breakpoints, and its length
+    fn disassemble_line(&self, machine: &Machine, address: u16) -> (String, u16) {
+        let pc_marker = if address == machine.cpu().program_counter().read_reg() {'>'} else {' '};
+        let break_marker = if self.breakpoints.contains(address) {'*'} else {' '};
+        let (text, bytes, length) = decode(machine, address);
+        (format!("{}{} 0x{:03X}: {:<8} {}", pc_marker, break_marker, address, bytes, text), length)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+//a breakpoint without a condition always stops
+fn triggers(condition: &Option<Expression>, machine: &Machine) -> bool {
+    condition.as_ref().is_none_or(|condition| condition.evaluate(machine.cpu()) != 0)
+}
+
+fn parse_address(arg: Option<&&str>) -> Result<u16, String> {
+    let arg = arg.ok_or("Expected an address")?;
+    match parse_number(arg) {
+        Some(address) if address <= 0xFFFF => Ok(address as u16),
+        _ => Err(format!("Invalid address '{}'", arg))
+    }
+}
+
+//the instruction at an address as text, its bytes in hex and its length
+fn decode(machine: &Machine, address: u16) -> (String, String, u16) {
+    let ram = machine.cpu().ram();
+    let opcode = ram.read_word(address);
+    match Instruction::decode_with_next(opcode, ram.read_word(address.wrapping_add(2))) {
+        Some(instruction) => {
+            let bytes: String = instruction.to_bytes().iter().map(|b| format!("{:02X}", b)).collect();
+            (instruction.to_string(), bytes, instruction.length())
+        }
+        None => (format!("dw 0x{:04X}", opcode), format!("{:04X}", opcode), 2)
+    }
+}
+
+fn add_watchpoint(machine: &mut Machine, args: &[&str], read: bool, write: bool) -> Result<String, String> {
+    let start = parse_address(args.first())?;
+    let length = match args.get(1) {
+        Some(length) => match parse_number(length) {
+            Some(length) if length > 0 && length <= 0x10000 - start as u32 => length as u16,
+            _ => return Err(format!("Invalid length '{}'", length))
+        },
+        None => 1
+    };
+    let end = start + (length - 1);
+    machine.cpu_mut().ram_mut().add_watchpoint(Watchpoint{start, end, read, write});
+    if start == end {
+        Ok(format!("Watchpoint set at 0x{:03X}", start))
+    } else {
+        Ok(format!("Watchpoint set at 0x{:03X}-0x{:03X}", start, end))
+    }
+}
+
+fn describe_hit(hit: &WatchHit, pc: u16, instruction: &str) -> String {
+    match hit.access {
+        Access::Read => format!("0x{:03X} read by 0x{:03X} {}: 0x{:02X}", hit.address, pc, instruction, hit.old),
+        Access::Write => format!("0x{:03X} written by 0x{:03X} {}: 0x{:02X} -> 0x{:02X}",
+                                 hit.address, pc, instruction, hit.old, hit.new)
+    }
+}
+
+//splits "0x2A4 if v3 == 1" into the address and whatever follows it
+fn split_address(rest: &str) -> Result<(u16, &str), String> {
+    let mut parts = rest.splitn(2, char::is_whitespace);
+    let address = parse_address(parts.next().filter(|address| !address.is_empty()).as_ref())?;
+    Ok((address, parts.next().unwrap_or("").trim()))
+}
+
+fn registers(machine: &Machine) -> String {
+    let cpu = machine.cpu();
+    let mut lines: Vec<String> = cpu.v().chunks(4)
+        .map(|row| row.iter()
+            .map(|reg| format!("{}: 0x{:02X}", reg.name(), reg.read_reg()))
+            .collect::<Vec<String>>()
+            .join("  "))
+        .collect();
+    for reg in [cpu.index(), cpu.program_counter()].iter() {
+        lines.push(format!("{}: 0x{:04X}", reg.name(), reg.read_reg()));
+    }
+    for reg in [cpu.stack_pointer(), cpu.delay_timer(), cpu.sound_timer()].iter() {
+        lines.push(format!("{}: 0x{:02X}", reg.name(), reg.read_reg()));
+    }
+    lines.join("\n")
+}
+
+fn stack(machine: &Machine) -> String {
+    let stack = machine.cpu().stack();
+    if stack.is_empty() {
+        return String::from("The stack is empty");
+    }
+    stack.iter()
+        .map(|reg| format!("{}: 0x{:04X}", reg.name(), reg.read_reg()))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn memory(machine: &Machine, args: &[&str]) -> Result<String, String> {
+    let start = parse_address(args.first())?;
+    let length = match args.get(1) {
+        Some(length) => parse_number(length).ok_or(format!("Invalid length '{}'", length))? as usize,
+        None => DEFAULT_MEMORY_LENGTH
+    };
+    let ram = machine.cpu().ram();
+    let lines: Vec<String> = (0..length).step_by(16)
+        .map(|offset| {
+            let address = start.wrapping_add(offset as u16);
+            let bytes: Vec<String> = (0..(length - offset).min(16))
+                .map(|i| format!("{:02X}", ram.peek(address.wrapping_add(i as u16))))
+                .collect();
+            format!("0x{:04X}: {}", address, bytes.join(" "))
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn set_register(machine: &mut Machine, args: &[&str]) -> Result<String, String> {
+    let (name, value) = match args {
+        [name, value] => (name.to_ascii_lowercase(), *value),
+        _ => return Err(String::from("Usage: set <reg> <value>"))
+    };
+    let value = match parse_number(value) {
+        Some(value) if value <= 0xFFFF => value as u16,
+        _ => return Err(format!("Invalid value '{}'", value))
+    };
+    let cpu = machine.cpu_mut();
+    match name.as_str() {
+        "i" => cpu.index_mut().write_reg(value),
+        "pc" => cpu.program_counter_mut().write_reg(value),
+        _ => {
+            let x = name.strip_prefix('v')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .ok_or(format!("Unknown register '{}'", name))?;
+            if value > 0xFF {
+                return Err(format!("0x{:X} does not fit in V{:X}", value, x));
+            }
+            cpu.v_mut()[x as usize].write_reg(value as u8);
+        }
+    }
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::quirks::Quirks;
+
+    fn machine(source: &str) -> Machine {
+        let mut machine = Machine::default();
+        machine.load_rom(&assemble(source).unwrap()).unwrap();
+        machine
+    }
+
+    fn pc(machine: &Machine) -> u16 {
+        machine.cpu().program_counter().read_reg()
+    }
+
+    #[test]
+    fn step_test() {
+        let mut machine = machine("LD V0, 1\nLD V1, 2\nLD V2, 3");
+        let mut debugger = Debugger::new();
+        assert!(debugger.check(&machine));
+        debugger.execute(&mut machine, "step");
+        assert_eq!(0x202, pc(&machine));
+        debugger.execute(&mut machine, "step 2");
+        assert_eq!(0x206, pc(&machine));
+        assert_eq!(3, machine.cpu().v()[2].read_reg());
+    }
+
+    #[test]
+    fn display_wait_step_test() {
+        //stepping a draw waiting for the vertical blank runs it rather than stalling forever
+        let mut machine = Machine::new(Quirks::vip());
+        machine.load_rom(&assemble("DRW V0, V0, 1\nDRW V0, V0, 1\nEXIT").unwrap()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "step");
+        assert_eq!(0x202, pc(&machine));
+        debugger.execute(&mut machine, "step");
+        assert_eq!(0x204, pc(&machine));
+        assert!(!machine.framebuffer().get(0, 0));
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let mut machine = machine("loop: ADD V0, 1\nJP loop");
+        let mut debugger = Debugger::new();
+        assert_eq!("Breakpoint set at 0x202", debugger.execute(&mut machine, "break 0x202"));
+        debugger.execute(&mut machine, "continue");
+        assert!(!debugger.check(&machine));
+        machine.step().unwrap();
+        assert!(debugger.check(&machine));
+        //continuing from the breakpoint runs it before stopping there again
+        debugger.execute(&mut machine, "c");
+        assert!(!debugger.check(&machine));
+        machine.step().unwrap();
+        assert!(!debugger.check(&machine));
+        machine.step().unwrap();
+        assert!(debugger.check(&machine));
+        assert_eq!(2, machine.cpu().v()[0].read_reg());
+    }
+
+    #[test]
+    fn delete_test() {
+        let mut machine = machine("CLS");
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "break 0x202");
+        debugger.execute(&mut machine, "b 0x204");
+        debugger.execute(&mut machine, "delete 0x202");
+        assert_eq!(vec![&0x204], debugger.breakpoints().collect::<Vec<_>>());
+        assert_eq!("No breakpoint, tracepoint or watchpoint at 0x202", debugger.execute(&mut machine, "delete 0x202"));
+        debugger.execute(&mut machine, "delete");
+        assert_eq!(0, debugger.breakpoints().count());
+    }
+
+    #[test]
+    fn regs_test() {
+        let mut machine = machine("LD VA, 0x2B\nLD I, 0x300");
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "step 2");
+        let regs = debugger.execute(&mut machine, "regs");
+        assert!(regs.contains("VA: 0x2B"));
+        assert!(regs.contains("Index: 0x0300"));
+        assert!(regs.contains("Program Counter: 0x0204"));
+    }
+
+    #[test]
+    fn stack_test() {
+        let mut machine = machine("CALL sub\nsub: CALL sub2\nsub2: RET");
+        let mut debugger = Debugger::new();
+        assert_eq!("The stack is empty", debugger.execute(&mut machine, "stack"));
+        debugger.execute(&mut machine, "step 2");
+        assert_eq!("Stack0: 0x0202\nStack1: 0x0204", debugger.execute(&mut machine, "stack"));
+    }
+
+    #[test]
+    fn mem_test() {
+        let mut machine = machine("db 0x01, 0x02, 0x03");
+        let mut debugger = Debugger::new();
+        assert_eq!("0x0200: 01 02 03", debugger.execute(&mut machine, "mem 0x200 3"));
+        let dump = debugger.execute(&mut machine, "mem 0x200 20");
+        assert_eq!(2, dump.lines().count());
+        assert_eq!("0x0210: 00 00 00 00", dump.lines().nth(1).unwrap());
+    }
+
+    #[test]
+    fn set_test() {
+        let mut machine = machine("CLS");
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "set v3 0x10");
+        debugger.execute(&mut machine, "set I 0x345");
+        debugger.execute(&mut machine, "set pc 0x300");
+        assert_eq!(0x10, machine.cpu().v()[3].read_reg());
+        assert_eq!(0x345, machine.cpu().index().read_reg());
+        assert_eq!(0x300, pc(&machine));
+        assert_eq!("0x100 does not fit in V3", debugger.execute(&mut machine, "set v3 0x100"));
+        assert_eq!("Unknown register 'vg'", debugger.execute(&mut machine, "set vg 1"));
+    }
+
+    #[test]
+    fn disasm_test() {
+        let mut machine = machine("CLS\nLD I, LONG 0x1234\nJP 0x200");
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "break 0x202");
+        let listing = debugger.execute(&mut machine, "disasm 0x200 3");
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(">  0x200: 00E0     CLS", lines[0]);
+        assert_eq!(" * 0x202: F0001234 LD I, LONG 0x1234", lines[1]);
+        assert_eq!("   0x206: 1200     JP 0x200", lines[2]);
+        assert_eq!(">  0x200: 00E0     CLS", debugger.location(&machine));
+    }
+
+    #[test]
+    fn errors_test() {
+        let mut machine = machine("RET");
+        let mut debugger = Debugger::new();
+        assert!(debugger.execute(&mut machine, "step").starts_with("Emulation stopped"));
+        assert_eq!("Invalid address 'nowhere'", debugger.execute(&mut machine, "break nowhere"));
+        assert_eq!("Unknown command 'jump', type help for a list", debugger.execute(&mut machine, "jump"));
+        debugger.execute(&mut machine, "quit");
+        assert!(debugger.quit_requested());
+    }
+
+    #[test]
+    fn conditional_breakpoint_test() {
+        let mut machine = machine("loop: ADD V3, 1\nJP loop");
+        let mut debugger = Debugger::new();
+        assert_eq!("Breakpoint set at 0x202 if v3 == 0x10 && dt == 0",
+                   debugger.execute(&mut machine, "break 0x202 if v3 == 0x10 && dt == 0"));
+        debugger.execute(&mut machine, "continue");
+        let mut executed = 0;
+        while !debugger.check(&machine) {
+            machine.step().unwrap();
+            executed += 1;
+        }
+        assert_eq!(31, executed);
+        assert_eq!(0x10, machine.cpu().v()[3].read_reg());
+        assert_eq!("Usage: break <addr> [if <expr>]", debugger.execute(&mut machine, "break 0x202 when v3"));
+        assert_eq!("Unknown value 'vz'", debugger.execute(&mut machine, "break 0x202 if vz"));
+    }
+
+    #[test]
+    fn tracepoint_test() {
+        let mut machine = machine("LD I, 0x2AB\nloop: ADD V5, 10\nJP loop");
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "trace 0x204 \"score={v5} I={i:x}\"");
+        debugger.execute(&mut machine, "step 3");
+        debugger.execute(&mut machine, "continue");
+        for _ in 0..2 {
+            assert!(!debugger.check(&machine));
+            machine.step().unwrap();
+        }
+        assert_eq!(vec!["0x204: score=10 I=2ab", "0x204: score=20 I=2ab"], debugger.take_log());
+        assert!(debugger.take_log().is_empty());
+        assert_eq!("Usage: trace <addr> \"<message>\"", debugger.execute(&mut machine, "trace 0x204 score"));
+    }
+
+    #[test]
+    fn print_test() {
+        let mut machine = machine("LD V2, 0x20");
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut machine, "step");
+        assert_eq!("33 (0x21)", debugger.execute(&mut machine, "print v2 + 1"));
+        assert_eq!("1 (0x1)", debugger.execute(&mut machine, "p ram[0x200] == 0x62"));
+    }
+
+    #[test]
+    fn watchpoint_test() {
+        let mut machine = machine("LD I, 0x300\nLD V0, 123\nLD B, V0\nLD [I], V0\nLD V0, [I]\nEXIT");
+        let mut debugger = Debugger::new();
+        assert_eq!("Watchpoint set at 0x301-0x302", debugger.execute(&mut machine, "watch 0x301 2"));
+        debugger.execute(&mut machine, "continue");
+        while !debugger.check(&machine) {
+            machine.step().unwrap();
+        }
+        assert_eq!(vec!["0x301 written by 0x204 LD B, V0: 0x00 -> 0x02",
+                        "0x302 written by 0x204 LD B, V0: 0x00 -> 0x03"], debugger.take_log());
+        assert_eq!(0x206, pc(&machine));
+        //reads are ignored by a write watchpoint, and the debugger's own reads never trigger one
+        debugger.execute(&mut machine, "rwatch 0x300");
+        debugger.execute(&mut machine, "mem 0x300");
+        assert_eq!("", debugger.execute(&mut machine, "step"));
+        assert!(debugger.take_log().is_empty());
+        debugger.execute(&mut machine, "step 5");
+        assert_eq!(vec!["0x300 read by 0x208 LD V0, [I]: 0x7B"], debugger.take_log());
+        assert_eq!(0x20A, pc(&machine));
+        debugger.execute(&mut machine, "delete 0x300");
+        assert_eq!(1, machine.cpu().ram().watchpoints().len());
+    }
+}
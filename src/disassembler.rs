@@ -0,0 +1,211 @@
+use crate::instruction::Instruction;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    //ordered by priority, a call target that is also jumped to is still a subroutine
+    Data,
+    Jump,
+    Subroutine
+}
+
+//a ROM split into code and data by following control flow from its entry point
+pub struct Disassembly {
+    origin: u16,
+    rom: Vec<u8>,
+    instructions: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, LabelKind>
+}
+
+impl Disassembly {
+    pub fn new(rom: &[u8], origin: u16) -> Disassembly {
+        let mut disassembly = Disassembly {
+            origin,
+            rom: rom.to_vec(),
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new()
+        };
+        disassembly.trace(origin);
+        disassembly
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        address >= self.origin && ((address - self.origin) as usize) < self.rom.len()
+    }
+
+    fn byte(&self, address: u16) -> u8 {
+        if self.contains(address) {self.rom[(address - self.origin) as usize]} else {0}
+    }
+
+    fn word(&self, address: u16) -> u16 {
+        ((self.byte(address) as u16) << 8) | self.byte(address.wrapping_add(1)) as u16
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        if self.contains(address) {
+            let entry = self.labels.entry(address).or_insert(kind);
+            *entry = (*entry).max(kind);
+        }
+    }
+
+    //recursive traversal, every path is followed until it jumps away, returns or hits data
+    fn trace(&mut self, entry: u16) {
+        use Instruction::*;
+
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop() {
+            if !self.contains(address) || self.instructions.contains_key(&address) {
+                continue;
+            }
+            let instruction = match Instruction::decode_with_next(self.word(address), self.word(address.wrapping_add(2))) {
+                Some(instruction) => instruction,
+                None => continue
+            };
+            self.instructions.insert(address, instruction);
+            let next = address.wrapping_add(instruction.length());
+            match instruction {
+                Jp(target) => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                Call(target) => {
+                    self.add_label(target, LabelKind::Subroutine);
+                    pending.push(target);
+                    pending.push(next);
+                }
+                //the offset is only known at run time, so the base is the best guess
+                JpV0(target) => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                Ret | Exit => {}
+                SeImm{..} | SneImm{..} | SeReg{..} | SneReg{..} | Skp{..} | Sknp{..} => {
+                    //the skipped instruction is four bytes long if it is F000 NNNN
+                    let skipped = if self.word(next) == 0xF000 {4} else {2};
+                    pending.push(next);
+                    pending.push(next.wrapping_add(skipped));
+                }
+                LdI(target) => {
+                    self.add_label(target, LabelKind::Data);
+                    pending.push(next);
+                }
+                LdILong(target) => {
+                    self.add_label(target, LabelKind::Data);
+                    pending.push(next);
+                }
+                _ => pending.push(next)
+            }
+        }
+    }
+
+    pub fn instruction_at(&self, address: u16) -> Option<Instruction> {
+        self.instructions.get(&address).copied()
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<String> {
+        self.labels.get(&address).map(|kind| {
+            let prefix = match kind {
+                LabelKind::Subroutine => "sub",
+                LabelKind::Jump => "label",
+                LabelKind::Data => "data"
+            };
+            format!("{}_{:03X}", prefix, address)
+        })
+    }
+
+    //the instruction with any address operand replaced by its label
+    fn format_instruction(&self, instruction: Instruction) -> String {
+        use Instruction::*;
+
+        let target = |address: u16| self.label_at(address).unwrap_or(format!("0x{:03X}", address));
+        match instruction {
+            Jp(address) => format!("JP {}", target(address)),
+            Call(address) => format!("CALL {}", target(address)),
+            JpV0(address) => format!("JP V0, {}", target(address)),
+            LdI(address) => format!("LD I, {}", target(address)),
+            LdILong(address) => format!("LD I, LONG {}", target(address)),
+            _ => instruction.to_string()
+        }
+    }
+}
+
+//draws a byte of sprite data as eight pixels
+fn sprite_row(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 {'#'} else {'.'}).collect()
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let end = self.origin as usize + self.rom.len();
+        let mut address = self.origin as usize;
+        while address < end {
+            let current = address as u16;
+            if let Some(label) = self.label_at(current) {
+                writeln!(f, "{}:", label)?;
+            }
+            match self.instruction_at(current) {
+                Some(instruction) => {
+                    let bytes: String = instruction.to_bytes().iter().map(|b| format!("{:02X}", b)).collect();
+                    writeln!(f, "    {:<24}; 0x{:03X}: {}", self.format_instruction(instruction), current, bytes)?;
+                    address += instruction.length() as usize;
+                }
+                None => {
+                    let byte = self.byte(current);
+                    writeln!(f, "    {:<24}; 0x{:03X}: {}", format!("db 0x{:02X}", byte), current, sprite_row(byte))?;
+                    address += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //CALL 0x206, JP 0x202, two bytes of sprite data, LD I 0x204, RET
+    const ROM: [u8; 10] = [0x22, 0x06, 0x12, 0x02, 0x3C, 0x42, 0xA2, 0x04, 0x00, 0xEE];
+
+    #[test]
+    fn code_and_data_test() {
+        let disassembly = Disassembly::new(&ROM, 0x200);
+        assert_eq!(Some(Instruction::Call(0x206)), disassembly.instruction_at(0x200));
+        assert_eq!(Some(Instruction::Jp(0x202)), disassembly.instruction_at(0x202));
+        assert_eq!(None, disassembly.instruction_at(0x204));
+        assert_eq!(Some(Instruction::LdI(0x204)), disassembly.instruction_at(0x206));
+        assert_eq!(Some(Instruction::Ret), disassembly.instruction_at(0x208));
+    }
+
+    #[test]
+    fn labels_test() {
+        let disassembly = Disassembly::new(&ROM, 0x200);
+        assert_eq!(Some(String::from("sub_206")), disassembly.label_at(0x206));
+        assert_eq!(Some(String::from("label_202")), disassembly.label_at(0x202));
+        assert_eq!(Some(String::from("data_204")), disassembly.label_at(0x204));
+        assert_eq!(None, disassembly.label_at(0x200));
+    }
+
+    #[test]
+    fn skip_test() {
+        //SE V0 0x00, JP 0x200, then a long load that is only reached by skipping
+        let rom = [0x30, 0x00, 0x12, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        let disassembly = Disassembly::new(&rom, 0x200);
+        assert_eq!(Some(Instruction::LdILong(0x1234)), disassembly.instruction_at(0x204));
+        assert_eq!(None, disassembly.instruction_at(0x206));
+        assert_eq!(Some(Instruction::Exit), disassembly.instruction_at(0x208));
+    }
+
+    #[test]
+    fn listing_test() {
+        let listing = Disassembly::new(&ROM, 0x200).to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!("    CALL sub_206            ; 0x200: 2206", lines[0]);
+        assert_eq!("label_202:", lines[1]);
+        assert_eq!("data_204:", lines[3]);
+        assert_eq!("    db 0x3C                 ; 0x204: ..####..", lines[4]);
+        assert_eq!("sub_206:", lines[6]);
+        assert_eq!("    LD I, data_204          ; 0x206: A204", lines[7]);
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    RomTooLarge{size: usize, max: usize},
+    Io(io::Error),
+    //errors raised while executing carry the address and opcode of the instruction
+    StackOverflow{pc: u16, opcode: u16},
+    StackUnderflow{pc: u16, opcode: u16},
+    InvalidOpcode{pc: u16, opcode: u16},
+    MemoryOutOfRange{pc: u16, opcode: u16, address: usize},
+    InvalidSaveState(String),
+    InvalidMovie(String),
+    //the final state hash of a played back movie did not match the recording
+    MovieDesync{expected: u64, actual: u64}
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::RomTooLarge{size, max} =>
+                write!(f, "ROM is {} bytes but at most {} bytes fit in memory", size, max),
+            EmulatorError::Io(error) => write!(f, "{}", error),
+            EmulatorError::StackOverflow{pc, opcode} =>
+                write!(f, "stack overflow at 0x{:03X} (opcode {:04X})", pc, opcode),
+            EmulatorError::StackUnderflow{pc, opcode} =>
+                write!(f, "return with an empty stack at 0x{:03X} (opcode {:04X})", pc, opcode),
+            EmulatorError::InvalidOpcode{pc, opcode} =>
+                write!(f, "invalid opcode {:04X} at 0x{:03X}", opcode, pc),
+            EmulatorError::MemoryOutOfRange{pc, opcode, address} =>
+                write!(f, "access to 0x{:X} is out of range at 0x{:03X} (opcode {:04X})", address, pc, opcode),
+            EmulatorError::InvalidSaveState(reason) => write!(f, "invalid save state: {}", reason),
+            EmulatorError::InvalidMovie(reason) => write!(f, "invalid movie: {}", reason),
+            EmulatorError::MovieDesync{expected, actual} =>
+                write!(f, "playback desynced, final state hash is {:016x} but the recording ended on {:016x}", actual, expected)
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EmulatorError::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(error: io::Error) -> EmulatorError {
+        EmulatorError::Io(error)
+    }
+}
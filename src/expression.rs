@@ -0,0 +1,461 @@
+use crate::assembler::parse_number;
+use crate::cpu::CPU;
+use std::fmt::Write;
+
+//values a debugger expression can read from the machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variable {
+    V(u8),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    DelayTimer,
+    SoundTimer
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Complement
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem
+}
+
+impl BinaryOp {
+    //C precedence, higher binds tighter
+    fn precedence(self) -> u8 {
+        use BinaryOp::*;
+
+        match self {
+            Or => 1,
+            And => 2,
+            BitOr => 3,
+            BitXor => 4,
+            BitAnd => 5,
+            Eq | Ne => 6,
+            Lt | Le | Gt | Ge => 7,
+            Shl | Shr => 8,
+            Add | Sub => 9,
+            Mul | Div | Rem => 10
+        }
+    }
+
+    fn from_token(token: &str) -> Option<BinaryOp> {
+        use BinaryOp::*;
+
+        match token {
+            "||" => Some(Or),
+            "&&" => Some(And),
+            "|" => Some(BitOr),
+            "^" => Some(BitXor),
+            "&" => Some(BitAnd),
+            "==" => Some(Eq),
+            "!=" => Some(Ne),
+            "<" => Some(Lt),
+            "<=" => Some(Le),
+            ">" => Some(Gt),
+            ">=" => Some(Ge),
+            "<<" => Some(Shl),
+            ">>" => Some(Shr),
+            "+" => Some(Add),
+            "-" => Some(Sub),
+            "*" => Some(Mul),
+            "/" => Some(Div),
+            "%" => Some(Rem),
+            _ => None
+        }
+    }
+}
+
+//a parsed expression such as `v3 == 0x10 && ram[i + 2] > dt`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Number(i64),
+    Variable(Variable),
+    Ram(Box<Expression>),
+    Key(Box<Expression>),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>)
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expression = parser.expression(0)?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("Unexpected '{}'", token))
+        }
+    }
+
+    //comparisons and logical operators give 1 for true and 0 for false
+    pub fn evaluate(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expression::Number(value) => *value,
+            Expression::Variable(variable) => read_variable(cpu, *variable),
+            Expression::Ram(address) => cpu.ram().peek(address.evaluate(cpu) as u16) as i64,
+            Expression::Key(key) => cpu.keys_pressed[(key.evaluate(cpu) & 0xF) as usize] as i64,
+            Expression::Unary(op, operand) => {
+                let value = operand.evaluate(cpu);
+                match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Complement => !value
+                }
+            }
+            Expression::Binary(op, left, right) => {
+                use BinaryOp::*;
+
+                let left = left.evaluate(cpu);
+                //short circuit so `&&` and `||` behave as they do in C
+                match op {
+                    And if left == 0 => return 0,
+                    Or if left != 0 => return 1,
+                    _ => {}
+                }
+                let right = right.evaluate(cpu);
+                match op {
+                    Or | And => (right != 0) as i64,
+                    BitOr => left | right,
+                    BitXor => left ^ right,
+                    BitAnd => left & right,
+                    Eq => (left == right) as i64,
+                    Ne => (left != right) as i64,
+                    Lt => (left < right) as i64,
+                    Le => (left <= right) as i64,
+                    Gt => (left > right) as i64,
+                    Ge => (left >= right) as i64,
+                    Shl => left.wrapping_shl(right as u32),
+                    Shr => left.wrapping_shr(right as u32),
+                    Add => left.wrapping_add(right),
+                    Sub => left.wrapping_sub(right),
+                    Mul => left.wrapping_mul(right),
+                    //a debugger should not crash the emulator, so dividing by zero gives zero
+                    Div => left.checked_div(right).unwrap_or(0),
+                    Rem => left.checked_rem(right).unwrap_or(0)
+                }
+            }
+        }
+    }
+}
+
+fn read_variable(cpu: &CPU, variable: Variable) -> i64 {
+    match variable {
+        Variable::V(x) => cpu.v()[x as usize].read_reg() as i64,
+        Variable::Index => cpu.index().read_reg() as i64,
+        Variable::ProgramCounter => cpu.program_counter().read_reg() as i64,
+        Variable::StackPointer => cpu.stack_pointer().read_reg() as i64,
+        Variable::DelayTimer => cpu.delay_timer().read_reg() as i64,
+        Variable::SoundTimer => cpu.sound_timer().read_reg() as i64
+    }
+}
+
+fn parse_variable(name: &str) -> Option<Variable> {
+    match name {
+        "i" => Some(Variable::Index),
+        "pc" => Some(Variable::ProgramCounter),
+        "sp" => Some(Variable::StackPointer),
+        "dt" => Some(Variable::DelayTimer),
+        "st" => Some(Variable::SoundTimer),
+        _ => {
+            let digit = name.strip_prefix('v').filter(|digit| digit.len() == 1)?;
+            u8::from_str_radix(digit, 16).ok().map(Variable::V)
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < chars.len() {
+        let c = chars[position];
+        if c.is_whitespace() {
+            position += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            tokens.push(chars[start..position].iter().collect::<String>().to_ascii_lowercase());
+        } else {
+            let pair: String = chars[position..(position + 2).min(chars.len())].iter().collect();
+            if ["==", "!=", "<=", ">=", "&&", "||", "<<", ">>"].contains(&pair.as_str()) {
+                tokens.push(pair);
+                position += 2;
+            } else if "+-*/%&|^!~<>()[]".contains(c) {
+                tokens.push(c.to_string());
+                position += 1;
+            } else {
+                return Err(format!("Unexpected character '{}'", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("Unexpected end of expression")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            ref token if token == expected => Ok(()),
+            token => Err(format!("Expected '{}' but found '{}'", expected, token))
+        }
+    }
+
+    //precedence climbing, only operators binding tighter than min_precedence are consumed
+    fn expression(&mut self, min_precedence: u8) -> Result<Expression, String> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek().and_then(BinaryOp::from_token) {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.expression(op.precedence())?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        let op = match self.peek() {
+            Some("-") => Some(UnaryOp::Neg),
+            Some("!") => Some(UnaryOp::Not),
+            Some("~") => Some(UnaryOp::Complement),
+            _ => None
+        };
+        match op {
+            Some(op) => {
+                self.position += 1;
+                Ok(Expression::Unary(op, Box::new(self.unary()?)))
+            }
+            None => self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let token = self.next()?;
+        match token.as_str() {
+            "(" => {
+                let expression = self.expression(0)?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            "ram" | "key" => {
+                self.expect("[")?;
+                let index = Box::new(self.expression(0)?);
+                self.expect("]")?;
+                Ok(if token == "ram" {Expression::Ram(index)} else {Expression::Key(index)})
+            }
+            _ => {
+                if let Some(variable) = parse_variable(&token) {
+                    Ok(Expression::Variable(variable))
+                } else if let Some(value) = parse_number(&token) {
+                    Ok(Expression::Number(value as i64))
+                } else {
+                    Err(format!("Unknown value '{}'", token))
+                }
+            }
+        }
+    }
+}
+
+//how a template field is printed, chosen with a suffix such as {i:x}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Radix {
+    Decimal,
+    LowerHex,
+    UpperHex,
+    Binary
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Field(Expression, Radix)
+}
+
+//a tracepoint message such as "score={v5} I={i:x}", braces are escaped by doubling them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err(String::from("Unclosed '{' in trace message"))
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Text(literal.clone()));
+                        literal.clear();
+                    }
+                    segments.push(parse_field(&field)?);
+                }
+                '}' => return Err(String::from("Unmatched '}' in trace message")),
+                _ => literal.push(c)
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Text(literal));
+        }
+        Ok(Template { segments })
+    }
+
+    pub fn render(&self, cpu: &CPU) -> String {
+        let mut output = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Field(expression, radix) => {
+                    let value = expression.evaluate(cpu);
+                    //writing to a String cannot fail
+                    let _ = match radix {
+                        Radix::Decimal => write!(output, "{}", value),
+                        Radix::LowerHex => write!(output, "{:x}", value),
+                        Radix::UpperHex => write!(output, "{:X}", value),
+                        Radix::Binary => write!(output, "{:b}", value)
+                    };
+                }
+            }
+        }
+        output
+    }
+}
+
+fn parse_field(field: &str) -> Result<Segment, String> {
+    let (expression, radix) = match field.rfind(':') {
+        Some(colon) => {
+            let radix = match &field[colon + 1..] {
+                "d" => Radix::Decimal,
+                "x" => Radix::LowerHex,
+                "X" => Radix::UpperHex,
+                "b" => Radix::Binary,
+                format => return Err(format!("Unknown format ':{}', expected d, x, X or b", format))
+            };
+            (&field[..colon], radix)
+        }
+        None => (field, Radix::Decimal)
+    };
+    Ok(Segment::Field(Expression::parse(expression)?, radix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use crate::ram::RAM;
+
+    fn cpu() -> CPU {
+        let mut ram = RAM::new();
+        ram.write_byte(0x300, 0x42);
+        let mut cpu = CPU::new(ram, Quirks::default());
+        cpu.v_mut()[3].write_reg(0x10);
+        cpu.v_mut()[5].write_reg(120);
+        cpu.index_mut().write_reg(0x2FE);
+        cpu.keys_pressed[0xA] = true;
+        cpu
+    }
+
+    fn evaluate(text: &str) -> i64 {
+        Expression::parse(text).unwrap().evaluate(&cpu())
+    }
+
+    #[test]
+    fn precedence_test() {
+        assert_eq!(7, evaluate("1 + 2 * 3"));
+        assert_eq!(9, evaluate("(1 + 2) * 3"));
+        assert_eq!(1, evaluate("1 + 1 == 2 && 3 > 2"));
+        assert_eq!(6, evaluate("1 << 2 | 2"));
+        assert_eq!(-4, evaluate("-2 * 2"));
+        assert_eq!(1, evaluate("!0"));
+    }
+
+    #[test]
+    fn variables_test() {
+        assert_eq!(1, evaluate("v3 == 0x10 && dt == 0"));
+        assert_eq!(0x2FE, evaluate("I"));
+        assert_eq!(0x200, evaluate("pc"));
+        assert_eq!(0x42, evaluate("ram[i + 2]"));
+        assert_eq!(1, evaluate("key[0xA]"));
+        assert_eq!(0, evaluate("key[v3 - 1]"));
+    }
+
+    #[test]
+    fn short_circuit_test() {
+        assert_eq!(0, evaluate("0 && 1 / 0"));
+        assert_eq!(1, evaluate("v5 || 0"));
+        assert_eq!(0, evaluate("5 / 0"));
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        assert_eq!(Err(String::from("Unknown value 'vg'")), Expression::parse("vg == 1"));
+        assert_eq!(Err(String::from("Unexpected end of expression")), Expression::parse("v3 =="));
+        assert_eq!(Err(String::from("Unexpected ')'")), Expression::parse("v3)"));
+        assert_eq!(Err(String::from("Unexpected character '$'")), Expression::parse("$"));
+    }
+
+    #[test]
+    fn template_test() {
+        let template = Template::parse("score={v5} I={i:x} {{raw}} {ram[0x300]:X}").unwrap();
+        assert_eq!("score=120 I=2fe {raw} 42", template.render(&cpu()));
+        assert!(Template::parse("{v5").is_err());
+        assert!(Template::parse("{v5:q}").is_err());
+    }
+}
@@ -0,0 +1,607 @@
+use crate::breakpoints::Breakpoints;
+use crate::cpu::CPU;
+use crate::machine::Machine;
+use crate::remote::RemoteDebugger;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+//stop signals reported to gdb
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+//V0-VF, I, PC, SP, DT and ST, in the order of the target description
+const REGISTER_COUNT: usize = 21;
+const INDEX_REGISTER: usize = 16;
+const PC_REGISTER: usize = 17;
+const SP_REGISTER: usize = 18;
+const DT_REGISTER: usize = 19;
+const ST_REGISTER: usize = 20;
+
+//largest packet we accept or send, advertised in qSupported
+const PACKET_SIZE: usize = 0x1000;
+//instructions run between checks for an interrupt, which each cost a system call
+const INTERRUPT_INTERVAL: u32 = 100;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.ferric8.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Paused,
+    Running,
+    //a single instruction is being executed from the address, stop again before the next one
+    Stepping{from: u16},
+    //gdb went away, the ROM keeps running on its own
+    Detached
+}
+
+//serves the GDB remote serial protocol, checked by the frontend before every instruction
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: Breakpoints<()>,
+    //bytes received while checking for an interrupt, not yet parsed
+    pending: VecDeque<u8>,
+    //instructions left to run before checking for an interrupt again
+    until_interrupt_check: u32,
+    state: State,
+    killed: bool
+}
+
+impl GdbStub {
+    //waits for gdb to connect to 127.0.0.1:port
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        GdbStub::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<GdbStub> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream,
+            breakpoints: Breakpoints::new(),
+            pending: VecDeque::new(),
+            until_interrupt_check: 0,
+            state: State::Paused,
+            killed: false
+        })
+    }
+
+    //true once gdb has sent a kill request, the frontend should stop emulating
+    pub fn killed(&self) -> bool {
+        self.killed
+    }
+
+    //called before every instruction, blocks serving gdb until it continues or steps
+    pub fn check(&mut self, machine: &mut Machine) -> io::Result<()> {
+        match self.state {
+            State::Detached => return Ok(()),
+            //a draw waiting for the vertical blank has not run yet, so the step goes on until it does
+            State::Stepping{from} if machine.stalled_draw(from) => {}
+            State::Stepping{..} => self.stop(SIGTRAP)?,
+            State::Running => {
+                let pc = machine.cpu().program_counter().read_reg();
+                let breakpoint = self.breakpoints.hit(pc, |_| true);
+                let poll = self.until_interrupt_check == 0;
+                if poll {
+                    self.until_interrupt_check = INTERRUPT_INTERVAL;
+                }
+                self.until_interrupt_check -= 1;
+                if poll && self.interrupt_requested()? {
+                    self.stop(SIGINT)?;
+                } else if breakpoint {
+                    self.stop(SIGTRAP)?;
+                }
+            }
+            State::Paused => {}
+        }
+        while self.state == State::Paused {
+            match self.read_packet()? {
+                Some(packet) => self.handle(&packet, machine)?,
+                None => self.state = State::Detached
+            }
+        }
+        Ok(())
+    }
+
+    //tells gdb the ROM has finished, with the status it should print
+    pub fn report_exit(&mut self, status: u8) -> io::Result<()> {
+        if self.state == State::Detached {
+            return Ok(());
+        }
+        self.state = State::Detached;
+        self.send(&format!("W{:02x}", status))
+    }
+
+    fn stop(&mut self, signal: u8) -> io::Result<()> {
+        self.state = State::Paused;
+        self.send(&format!("S{:02x}", signal))
+    }
+
+    //answers one packet, continue and step only reply once the machine stops again
+    fn handle(&mut self, packet: &str, machine: &mut Machine) -> io::Result<()> {
+        let cpu = machine.cpu_mut();
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => format!("S{:02x}", SIGTRAP),
+            Some(b'g') => (0..REGISTER_COUNT).map(|n| read_register(cpu, n)).collect(),
+            Some(b'G') => write_registers(cpu, &packet[1..]),
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < REGISTER_COUNT => read_register(cpu, n),
+                _ => String::from("E01")
+            },
+            Some(b'P') => write_register_packet(cpu, &packet[1..]),
+            Some(b'm') => read_memory(cpu, &packet[1..]),
+            Some(b'M') => write_memory(cpu, &packet[1..]),
+            Some(b'Z') | Some(b'z') => self.breakpoint(packet),
+            Some(b'c') | Some(b's') => {
+                //an optional address to resume from
+                if packet.len() > 1 {
+                    match u16::from_str_radix(&packet[1..], 16) {
+                        Ok(address) => cpu.program_counter_mut().write_reg(address),
+                        Err(_) => return self.send("E01")
+                    }
+                }
+                if packet.starts_with('c') {
+                    self.state = State::Running;
+                    self.breakpoints.resume(cpu.program_counter().read_reg());
+                } else {
+                    self.state = State::Stepping{from: cpu.program_counter().read_reg()};
+                }
+                return Ok(());
+            }
+            Some(b'k') => {
+                self.killed = true;
+                self.state = State::Detached;
+                return Ok(());
+            }
+            Some(b'D') => {
+                self.state = State::Detached;
+                String::from("OK")
+            }
+            Some(b'H') => String::from("OK"),
+            Some(b'q') => query(packet),
+            _ => String::new()
+        };
+        self.send(&reply)
+    }
+
+    //Z0 and Z1 set software and hardware breakpoints, which are the same thing here
+    fn breakpoint(&mut self, packet: &str) -> String {
+        let fields: Vec<&str> = packet[1..].split(',').collect();
+        let address = match fields.as_slice() {
+            ["0", address, _] | ["1", address, _] => u16::from_str_radix(address, 16),
+            _ => return String::new()
+        };
+        match address {
+            Ok(address) => {
+                if packet.starts_with('Z') {
+                    self.breakpoints.insert(address, ());
+                } else {
+                    self.breakpoints.remove(address);
+                }
+                String::from("OK")
+            }
+            Err(_) => String::from("E01")
+        }
+    }
+
+    //gdb interrupts a running target by sending a lone 0x03 outside of any packet
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 256];
+        let result = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => self.state = State::Detached,
+            Ok(length) => self.pending.extend(&buffer[..length]),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {}
+            Err(error) => return Err(error)
+        }
+        match self.pending.iter().position(|&byte| byte == 0x03) {
+            Some(position) => {
+                self.pending.remove(position);
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0]))
+        }
+    }
+
+    //reads a $data#checksum packet and acknowledges it, None once gdb disconnects
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            //acks, interrupts and line noise between packets are skipped
+            loop {
+                match self.next_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => {}
+                    None => return Ok(None)
+                }
+            }
+            let mut data = Vec::new();
+            let mut too_long = false;
+            loop {
+                match self.next_byte()? {
+                    Some(b'#') => break,
+                    Some(_) if data.len() == PACKET_SIZE => too_long = true,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None)
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                match self.next_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(None)
+                }
+            }
+            //gdb was told the packet size, so anything longer is refused rather than buffered
+            if too_long {
+                self.stream.write_all(b"+")?;
+                self.send("E01")?;
+                continue;
+            }
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let escaped = escape(data.as_bytes());
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(&escaped)).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+}
+
+impl RemoteDebugger for GdbStub {
+    fn check(&mut self, machine: &mut Machine) -> io::Result<()> {
+        GdbStub::check(self, machine)
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.killed
+    }
+
+    fn report_error(&mut self, _message: &str) -> io::Result<()> {
+        self.report_exit(1)
+    }
+
+    fn client_name(&self) -> &'static str {
+        "gdb"
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+//'#', '$', '}' and '*' are sent as '}' followed by the byte xor 0x20
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        if [b'#', b'$', b'}', b'*'].contains(&byte) {
+            escaped.push(b'}');
+            escaped.push(byte ^ 0x20);
+        } else {
+            escaped.push(byte);
+        }
+    }
+    escaped
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => unescaped.push(byte)
+        }
+    }
+    unescaped
+}
+
+//an odd number of digits fails because the last pair is cut short
+fn hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn register_size(n: usize) -> usize {
+    if n == INDEX_REGISTER || n == PC_REGISTER {2} else {1}
+}
+
+//16 bit registers are sent little endian, the byte order gdb assumes without a CHIP-8 architecture
+fn read_register(cpu: &CPU, n: usize) -> String {
+    let value = match n {
+        0..=15 => cpu.v()[n].read_reg() as u16,
+        INDEX_REGISTER => cpu.index().read_reg(),
+        PC_REGISTER => cpu.program_counter().read_reg(),
+        SP_REGISTER => cpu.stack_pointer().read_reg() as u16,
+        DT_REGISTER => cpu.delay_timer().read_reg() as u16,
+        _ => cpu.sound_timer().read_reg() as u16
+    };
+    value.to_le_bytes()[..register_size(n)].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+//the stack pointer is read only, moving it would leave the stack inconsistent
+fn write_register(cpu: &mut CPU, n: usize, bytes: &[u8]) {
+    let value = bytes.iter().rev().fold(0u16, |value, byte| (value << 8) | *byte as u16);
+    match n {
+        0..=15 => cpu.v_mut()[n].write_reg(value as u8),
+        INDEX_REGISTER => cpu.index_mut().write_reg(value),
+        PC_REGISTER => cpu.program_counter_mut().write_reg(value),
+        DT_REGISTER => cpu.delay_timer_mut().write_reg(value as u8),
+        ST_REGISTER => cpu.sound_timer_mut().write_reg(value as u8),
+        _ => {}
+    }
+}
+
+fn write_registers(cpu: &mut CPU, hex: &str) -> String {
+    let bytes = match hex_bytes(hex) {
+        Some(bytes) if bytes.len() == (0..REGISTER_COUNT).map(register_size).sum::<usize>() => bytes,
+        _ => return String::from("E01")
+    };
+    let mut offset = 0;
+    for n in 0..REGISTER_COUNT {
+        write_register(cpu, n, &bytes[offset..offset + register_size(n)]);
+        offset += register_size(n);
+    }
+    String::from("OK")
+}
+
+//P<n>=<value>
+fn write_register_packet(cpu: &mut CPU, text: &str) -> String {
+    let mut parts = text.splitn(2, '=');
+    let n = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+    let bytes = parts.next().and_then(hex_bytes);
+    match (n, bytes) {
+        (Some(n), Some(bytes)) if n < REGISTER_COUNT && bytes.len() == register_size(n) => {
+            write_register(cpu, n, &bytes);
+            String::from("OK")
+        }
+        _ => String::from("E01")
+    }
+}
+
+//addr,length with both in hex
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let address = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let length = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, length))
+}
+
+fn read_memory(cpu: &CPU, text: &str) -> String {
+    match parse_range(text) {
+        //each byte takes two hex digits, and the reply has to fit in a packet
+        Some((_, length)) if length > PACKET_SIZE / 2 => String::from("E01"),
+        Some((address, length)) => (0..length)
+            .map(|i| format!("{:02x}", cpu.ram().peek(address.wrapping_add(i as u16))))
+            .collect(),
+        None => String::from("E01")
+    }
+}
+
+//M<addr>,<length>:<bytes>
+fn write_memory(cpu: &mut CPU, text: &str) -> String {
+    let mut parts = text.splitn(2, ':');
+    let range = parts.next().and_then(parse_range);
+    let bytes = parts.next().and_then(hex_bytes);
+    match (range, bytes) {
+        (Some((address, length)), Some(bytes)) if bytes.len() == length => {
+            for (i, byte) in bytes.iter().enumerate() {
+                cpu.ram_mut().poke(address.wrapping_add(i as u16), *byte);
+            }
+            String::from("OK")
+        }
+        _ => String::from("E01")
+    }
+}
+
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+    } else if let Some(annex) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        match parse_range(annex) {
+            Some((offset, length)) => {
+                let offset = (offset as usize).min(TARGET_XML.len());
+                let end = offset.saturating_add(length).min(TARGET_XML.len());
+                //'l' marks the last chunk, 'm' means there is more to read
+                let marker = if end == TARGET_XML.len() {'l'} else {'m'};
+                format!("{}{}", marker, &TARGET_XML[offset..end])
+            }
+            None => String::from("E01")
+        }
+    } else if packet == "qAttached" {
+        String::from("1")
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use std::thread;
+
+    struct Client {
+        stream: TcpStream
+    }
+
+    impl Client {
+        fn send(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(b'+', ack[0]);
+        }
+
+        fn receive(&mut self) -> String {
+            let mut data = Vec::new();
+            let mut byte = [0];
+            while byte[0] != b'$' {
+                self.stream.read_exact(&mut byte).unwrap();
+            }
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(format!("{:02x}", checksum_of(&data)).as_bytes(), &checksum);
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(unescape(&data)).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.receive()
+        }
+    }
+
+    //connects a client to a stub driving the machine on another thread, like the frontend would
+    fn connect(source: &str) -> (Client, thread::JoinHandle<Machine>) {
+        let mut machine = Machine::default();
+        machine.load_rom(&assemble(source).unwrap()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = Client { stream: TcpStream::connect(listener.local_addr().unwrap()).unwrap() };
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        let handle = thread::spawn(move || {
+            loop {
+                stub.check(&mut machine).unwrap();
+                if stub.killed() {
+                    break;
+                }
+                machine.step().unwrap();
+                if machine.exited() {
+                    stub.report_exit(0).unwrap();
+                    break;
+                }
+            }
+            machine
+        });
+        (client, handle)
+    }
+
+    #[test]
+    fn registers_and_memory_test() {
+        let (mut client, handle) = connect("LD V0, 0x12\nLD I, 0x345\nEXIT");
+        assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert_eq!("S05", client.request("?"));
+        assert_eq!("S05", client.request("s"));
+        assert_eq!("S05", client.request("s"));
+        let registers = client.request("g");
+        assert_eq!(2 * (16 + 2 + 2 + 3), registers.len());
+        assert!(registers.starts_with("12000000"));
+        assert_eq!("4503", client.request("p10"));
+        assert_eq!("0402", client.request("p11"));
+        assert_eq!("OK", client.request("P5=7f"));
+        assert_eq!("7f", client.request("p5"));
+        assert_eq!("6012a3", client.request("m200,3"));
+        assert_eq!("OK", client.request("M300,2:beef"));
+        assert_eq!("beef", client.request("m300,2"));
+        assert_eq!("E01", client.request("m0,ffffffff"));
+        client.send("k");
+        let machine = handle.join().unwrap();
+        assert_eq!(0x7F, machine.cpu().v()[5].read_reg());
+        assert_eq!(0xBE, machine.cpu().ram().peek(0x300));
+    }
+
+    #[test]
+    fn long_packet_test() {
+        let (mut client, handle) = connect("EXIT");
+        let bytes = "ff".repeat(PACKET_SIZE);
+        assert_eq!("E01", client.request(&format!("M300,{:x}:{}", PACKET_SIZE, bytes)));
+        assert_eq!("S05", client.request("?"));
+        client.send("k");
+        assert_eq!(0, handle.join().unwrap().cpu().ram().peek(0x300));
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let (mut client, handle) = connect("loop: ADD V1, 1\nSE V1, 3\nJP loop\nEXIT");
+        assert_eq!("OK", client.request("Z0,202,2"));
+        assert_eq!("S05", client.request("c"));
+        assert_eq!("01", client.request("p1"));
+        assert_eq!("S05", client.request("c"));
+        assert_eq!("02", client.request("p1"));
+        assert_eq!("OK", client.request("z0,202,2"));
+        assert_eq!("W00", client.request("c"));
+        assert_eq!(3, handle.join().unwrap().cpu().v()[1].read_reg());
+    }
+
+    #[test]
+    fn interrupt_test() {
+        let (mut client, handle) = connect("loop: JP loop");
+        client.send("c");
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!("S02", client.receive());
+        assert_eq!("0002", client.request("p11"));
+        client.send("k");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn target_xml_test() {
+        let (mut client, handle) = connect("EXIT");
+        let first = client.request("qXfer:features:read:target.xml:0,40");
+        assert!(first.starts_with("m<?xml"));
+        assert_eq!(0x41, first.len());
+        let rest = client.request(&format!("qXfer:features:read:target.xml:40,{:x}", TARGET_XML.len()));
+        assert!(rest.starts_with('l'));
+        assert_eq!(TARGET_XML, format!("{}{}", &first[1..], &rest[1..]));
+        assert!(client.request("qXfer:features:read:target.xml:1,ffffffffffffffff").starts_with('l'));
+        client.send("D");
+        assert_eq!("OK", client.receive());
+        assert!(handle.join().unwrap().exited());
+    }
+}
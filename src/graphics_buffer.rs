@@ -1,59 +1,202 @@
+#[cfg(feature = "sdl")]
 use sdl2::pixels::Color;
+#[cfg(feature = "sdl")]
 use sdl2::render::WindowCanvas;
+#[cfg(feature = "sdl")]
 use sdl2::rect::Rect;
+use crate::error::EmulatorError;
+use crate::save_state::{StateReader, StateWriter};
 
+#[cfg(feature = "sdl")]
 const PIXEL_WIDTH: u32 = 16;
 const WIDTH: usize = 64;
 const HEIGHT: usize = 32;
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
+pub const PLANES: usize = 2;
+
+//RGB colours indexed by the plane bits of a pixel
+pub type Palette = [u32; 1 << PLANES];
+pub const DEFAULT_PALETTE: Palette = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
+
+//parses a comma separated list of hex colours such as "000000,ffffff,aaaaaa,555555"
+pub fn parse_palette(text: &str) -> Option<Palette>{
+    let mut palette = DEFAULT_PALETTE;
+    let colours: Vec<&str> = text.split(',').collect();
+    if colours.len() != palette.len(){
+        return None;
+    }
+    for (entry, colour) in palette.iter_mut().zip(colours){
+        let colour = colour.trim().trim_start_matches('#');
+        if colour.len() != 6{
+            return None;
+        }
+        *entry = u32::from_str_radix(colour, 16).ok()?;
+    }
+    Some(palette)
+}
 
 pub struct GraphicsBuffer{
-    pixels: [[bool; WIDTH];HEIGHT]
+    //sized for high resolution, low resolution only uses the top left corner
+    //each pixel holds one bit per plane
+    pixels: [[u8; HIRES_WIDTH];HIRES_HEIGHT],
+    hires: bool,
+    //XO-CHIP plane mask set by FN01
+    planes: u8
 }
 
 impl GraphicsBuffer{
     pub fn new() -> GraphicsBuffer{
-        let gfx = GraphicsBuffer{
-            pixels: [[false; WIDTH];HEIGHT]
-        };
-        gfx
+        GraphicsBuffer{
+            pixels: [[0; HIRES_WIDTH];HIRES_HEIGHT],
+            hires: false,
+            planes: 1
+        }
+    }
+
+    pub fn width(&self) -> usize{
+        if self.hires {HIRES_WIDTH} else {WIDTH}
+    }
+
+    pub fn height(&self) -> usize{
+        if self.hires {HIRES_HEIGHT} else {HEIGHT}
+    }
+
+    pub fn is_hires(&self) -> bool{
+        self.hires
+    }
+
+    //switching resolution clears every plane, not just the selected ones
+    pub fn set_hires(&mut self, hires: bool){
+        self.hires = hires;
+        self.pixels = [[0; HIRES_WIDTH];HIRES_HEIGHT];
+    }
+
+    pub fn selected_planes(&self) -> u8{
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8){
+        self.planes = planes & ((1 << PLANES) - 1) as u8;
     }
 
     pub fn get(&self, x: u8, y: u8) -> bool{
-        self.pixels[y as usize][x as usize]
+        self.get_plane(0, x, y)
     }
 
     pub fn toggle(&mut self, x: u8, y: u8){
-        self.pixels[y as usize][x as usize] = !self.pixels[y as usize][x as usize];
+        self.toggle_plane(0, x, y);
+    }
+
+    pub fn get_plane(&self, plane: usize, x: u8, y: u8) -> bool{
+        self.pixels[y as usize][x as usize] & (1 << plane) != 0
+    }
+
+    pub fn toggle_plane(&mut self, plane: usize, x: u8, y: u8){
+        self.pixels[y as usize][x as usize] ^= 1 << plane;
+    }
+
+    //palette index of a pixel, combining all planes
+    pub fn colour(&self, x: u8, y: u8) -> u8{
+        self.pixels[y as usize][x as usize]
     }
 
     pub fn clear(&mut self){
-        for i in 0..HEIGHT{
-            for j in 0..WIDTH{
-                self.pixels[i][j] = false;
+        let mask = !self.planes;
+        for i in 0..HIRES_HEIGHT{
+            for j in 0..HIRES_WIDTH{
+                self.pixels[i][j] &= mask;
+            }
+        }
+    }
+
+    //moves the selected planes of a pixel from one location to another
+    fn shift_pixel(&mut self, to: (usize, usize), from: Option<(usize, usize)>){
+        let moved = match from {
+            Some((i, j)) => self.pixels[i][j] & self.planes,
+            None => 0
+        };
+        self.pixels[to.0][to.1] = (self.pixels[to.0][to.1] & !self.planes) | moved;
+    }
+
+    pub fn scroll_down(&mut self, rows: usize){
+        let height = self.height();
+        for i in (0..height).rev(){
+            for j in 0..self.width(){
+                let from = if i >= rows {Some((i - rows, j))} else {None};
+                self.shift_pixel((i, j), from);
             }
         }
     }
 
+    pub fn scroll_right(&mut self, columns: usize){
+        let width = self.width();
+        for i in 0..self.height(){
+            for j in (0..width).rev(){
+                let from = if j >= columns {Some((i, j - columns))} else {None};
+                self.shift_pixel((i, j), from);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, columns: usize){
+        let width = self.width();
+        for i in 0..self.height(){
+            for j in 0..width{
+                let from = if j + columns < width {Some((i, j + columns))} else {None};
+                self.shift_pixel((i, j), from);
+            }
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter){
+        writer.write_bool(self.hires);
+        writer.write_u8(self.planes);
+        for row in self.pixels.iter(){
+            writer.write_bytes(row);
+        }
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<GraphicsBuffer, EmulatorError>{
+        let mut gfx = GraphicsBuffer::new();
+        gfx.hires = reader.read_bool()?;
+        gfx.select_planes(reader.read_u8()?);
+        let mask = ((1 << PLANES) - 1) as u8;
+        for row in gfx.pixels.iter_mut(){
+            for (pixel, saved) in row.iter_mut().zip(reader.read_bytes(HIRES_WIDTH)?){
+                *pixel = saved & mask;
+            }
+        }
+        Ok(gfx)
+    }
+
     pub fn draw_to_console(&self){
-        for i in 0..HEIGHT{
-            for j in 0..WIDTH{
-                print!("{}", if self.pixels[i][j] {'█'} else {'░'});
+        for i in 0..self.height(){
+            for j in 0..self.width(){
+                print!("{}", ['░', '█', '▒', '▓'][self.pixels[i][j] as usize]);
             }
             println!();
         }
     }
 
-    pub fn render(&mut self, canvas: &mut WindowCanvas){
-        canvas.set_draw_color(Color::BLACK);
+    #[cfg(feature = "sdl")]
+    pub fn render(&self, canvas: &mut WindowCanvas, palette: &Palette){
+        //keep the window the same size in both resolutions
+        let pixel_width = PIXEL_WIDTH * WIDTH as u32 / self.width() as u32;
+        let colours: Vec<Color> = palette.iter()
+            .map(|rgb| Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, *rgb as u8))
+            .collect();
+        canvas.set_draw_color(colours[0]);
         canvas.clear();
-        canvas.set_draw_color(Color::WHITE);
-        for i in 0..WIDTH{
-            for j in 0..HEIGHT{
-                if self.get(i as u8, j as u8){
+        for i in 0..self.width(){
+            for j in 0..self.height(){
+                let colour = self.colour(i as u8, j as u8);
+                if colour != 0{
+                    canvas.set_draw_color(colours[colour as usize]);
                     canvas.fill_rect(
-                        Rect::new((PIXEL_WIDTH * i as u32) as i32, (PIXEL_WIDTH * j as u32) as i32,
-                                  PIXEL_WIDTH, PIXEL_WIDTH)
-                    );
+                        Rect::new((pixel_width * i as u32) as i32, (pixel_width * j as u32) as i32,
+                                  pixel_width, pixel_width)
+                    ).expect("Could not draw pixel");
                 }
             }
         }
@@ -61,6 +204,12 @@ impl GraphicsBuffer{
     }
 }
 
+impl Default for GraphicsBuffer {
+    fn default() -> GraphicsBuffer {
+        GraphicsBuffer::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,4 +238,74 @@ mod tests {
         assert_eq!(false, gfx.get(0,0));
         assert_eq!(false, gfx.get(0xF,0xF));
     }
+
+    #[test]
+    fn hires_test() {
+        let mut gfx = GraphicsBuffer::new();
+        assert_eq!((64, 32), (gfx.width(), gfx.height()));
+        gfx.toggle(0,0);
+        gfx.set_hires(true);
+        assert_eq!((128, 64), (gfx.width(), gfx.height()));
+        assert!(!gfx.get(0,0));
+        gfx.toggle(127,63);
+        assert!(gfx.get(127,63));
+    }
+
+    #[test]
+    fn scroll_down_test() {
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle(5,0);
+        gfx.toggle(5,31);
+        gfx.scroll_down(3);
+        assert!(!gfx.get(5,0));
+        assert!(gfx.get(5,3));
+        assert!(!gfx.get(5,31));
+    }
+
+    #[test]
+    fn scroll_left_right_test() {
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle(0,1);
+        gfx.toggle(10,1);
+        gfx.scroll_right(4);
+        assert!(gfx.get(4,1));
+        assert!(gfx.get(14,1));
+        assert!(!gfx.get(0,1));
+        gfx.scroll_left(8);
+        assert!(gfx.get(6,1));
+        assert!(!gfx.get(14,1));
+    }
+
+    #[test]
+    fn planes_test() {
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle_plane(0, 2, 2);
+        gfx.toggle_plane(1, 2, 2);
+        gfx.toggle_plane(1, 3, 2);
+        assert_eq!(3, gfx.colour(2, 2));
+        assert_eq!(2, gfx.colour(3, 2));
+        gfx.select_planes(2);
+        gfx.clear();
+        assert_eq!(1, gfx.colour(2, 2));
+        assert_eq!(0, gfx.colour(3, 2));
+    }
+
+    #[test]
+    fn scroll_selected_planes_test() {
+        let mut gfx = GraphicsBuffer::new();
+        gfx.toggle_plane(0, 0, 0);
+        gfx.toggle_plane(1, 0, 0);
+        gfx.select_planes(2);
+        gfx.scroll_right(4);
+        assert_eq!(1, gfx.colour(0, 0));
+        assert_eq!(2, gfx.colour(4, 0));
+    }
+
+    #[test]
+    fn parse_palette_test() {
+        assert_eq!(Some([0x000000, 0xFF0000, 0x00FF00, 0x0000FF]),
+                   parse_palette("000000,ff0000,#00ff00,0000FF"));
+        assert_eq!(None, parse_palette("000000,ffffff"));
+        assert_eq!(None, parse_palette("000000,ffffff,zzzzzz,000000"));
+    }
 }
\ No newline at end of file
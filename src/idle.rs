@@ -0,0 +1,121 @@
+use crate::instruction::Instruction;
+
+//one pass around a loop that changed nothing, so every further pass until the next
+//timer tick or key change will run exactly the same way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdleLoop {
+    pub instructions: u32,
+    pub cycles: u32
+}
+
+//the registers a loop could change without touching anything else
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub index: u16
+}
+
+//a loop being watched: where it jumped back from and to, and what it has done since
+struct Probe {
+    start: u16,
+    end: u16,
+    registers: Registers,
+    instructions: u32,
+    cycles: u32,
+    side_effects: bool
+}
+
+//spots busy waits like polling the delay timer or FX0A waiting for a key, by watching for a
+//backward jump that arrives at the same place with the same registers and nothing else changed
+#[derive(Default)]
+pub struct IdleDetector {
+    probe: Option<Probe>
+}
+
+//instructions that change memory, the screen, timers, the stack or the random sequence
+//reads of the timers and keypad are fine, they cannot change until the frame ends
+fn has_side_effects(instruction: &Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(instruction, Sys(_) | Cls | Ret | Call(_) | Scd(_) | Scr | Scl | Exit | Low | High
+        | Save{..} | Rnd{..} | Drw{..} | Plane(_) | Audio | LdDtVx{..} | LdStVx{..} | LdB{..}
+        | Pitch{..} | LdIVx{..} | LdRVx{..})
+}
+
+impl IdleDetector {
+    pub fn new() -> IdleDetector {
+        IdleDetector { probe: None }
+    }
+
+    //call after every instruction
+    pub fn observe(&mut self, instruction: &Instruction, cycles: u32) {
+        if let Some(probe) = self.probe.as_mut() {
+            probe.instructions = probe.instructions.saturating_add(1);
+            probe.cycles = probe.cycles.saturating_add(cycles);
+            probe.side_effects |= has_side_effects(instruction);
+        }
+    }
+
+    //call when the instruction at end left the program counter at or before itself
+    pub fn loop_back(&mut self, end: u16, start: u16, registers: Registers) -> Option<IdleLoop> {
+        if let Some(probe) = self.probe.as_mut() {
+            if probe.start == start && probe.end == end && probe.registers == registers && !probe.side_effects {
+                let idle = IdleLoop { instructions: probe.instructions, cycles: probe.cycles };
+                probe.instructions = 0;
+                probe.cycles = 0;
+                return Some(idle);
+            }
+        }
+        self.probe = Some(Probe { start, end, registers, instructions: 0, cycles: 0, side_effects: false });
+        None
+    }
+
+    //forget the loop being watched, once the timers or keys it may be polling change
+    pub fn reset(&mut self) {
+        self.probe = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registers(v0: u8) -> Registers {
+        let mut v = [0; 16];
+        v[0] = v0;
+        Registers { v, index: 0x300 }
+    }
+
+    #[test]
+    fn loop_test() {
+        let mut detector = IdleDetector::new();
+        assert_eq!(None, detector.loop_back(0x204, 0x200, registers(5)));
+        detector.observe(&Instruction::LdVxDt{x: 0}, 50);
+        detector.observe(&Instruction::SeImm{x: 0, kk: 0}, 50);
+        detector.observe(&Instruction::Jp(0x200), 52);
+        assert_eq!(Some(IdleLoop { instructions: 3, cycles: 152 }), detector.loop_back(0x204, 0x200, registers(5)));
+
+        //a register that changes each pass, or a different loop, starts over
+        detector.observe(&Instruction::Jp(0x200), 52);
+        assert_eq!(None, detector.loop_back(0x204, 0x200, registers(6)));
+        detector.observe(&Instruction::Jp(0x200), 52);
+        assert_eq!(None, detector.loop_back(0x206, 0x200, registers(6)));
+        detector.observe(&Instruction::Jp(0x200), 52);
+        assert_eq!(Some(IdleLoop { instructions: 1, cycles: 52 }), detector.loop_back(0x206, 0x200, registers(6)));
+
+        detector.reset();
+        assert_eq!(None, detector.loop_back(0x206, 0x200, registers(6)));
+    }
+
+    #[test]
+    fn side_effects_test() {
+        let mut detector = IdleDetector::new();
+        detector.loop_back(0x202, 0x200, registers(0));
+        detector.observe(&Instruction::Drw{x: 0, y: 0, n: 1}, 100);
+        detector.observe(&Instruction::Jp(0x200), 52);
+        assert_eq!(None, detector.loop_back(0x202, 0x200, registers(0)));
+        detector.observe(&Instruction::Skp{x: 0}, 54);
+        detector.observe(&Instruction::Jp(0x200), 52);
+        assert!(detector.loop_back(0x202, 0x200, registers(0)).is_some());
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+
+//a decoded instruction, x and y are register numbers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(u16),
+    Cls,
+    Ret,
+    Scd(u8),
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    SeImm{x: u8, kk: u8},
+    SneImm{x: u8, kk: u8},
+    SeReg{x: u8, y: u8},
+    Save{x: u8, y: u8},
+    Load{x: u8, y: u8},
+    LdImm{x: u8, kk: u8},
+    AddImm{x: u8, kk: u8},
+    LdReg{x: u8, y: u8},
+    Or{x: u8, y: u8},
+    And{x: u8, y: u8},
+    Xor{x: u8, y: u8},
+    AddReg{x: u8, y: u8},
+    Sub{x: u8, y: u8},
+    Shr{x: u8, y: u8},
+    Subn{x: u8, y: u8},
+    Shl{x: u8, y: u8},
+    SneReg{x: u8, y: u8},
+    LdI(u16),
+    JpV0(u16),
+    Rnd{x: u8, kk: u8},
+    Drw{x: u8, y: u8, n: u8},
+    Skp{x: u8},
+    Sknp{x: u8},
+    //F000 NNNN, the only instruction that is two words long
+    LdILong(u16),
+    Plane(u8),
+    Audio,
+    LdVxDt{x: u8},
+    LdVxK{x: u8},
+    LdDtVx{x: u8},
+    LdStVx{x: u8},
+    AddI{x: u8},
+    LdF{x: u8},
+    LdHf{x: u8},
+    LdB{x: u8},
+    Pitch{x: u8},
+    LdIVx{x: u8},
+    LdVxI{x: u8},
+    LdRVx{x: u8},
+    LdVxR{x: u8}
+}
+
+impl Instruction {
+    //decodes a single word, F000 decodes to LdILong(0) as its address is in the next word
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        Instruction::decode_with_next(opcode, 0)
+    }
+
+    //decodes an instruction given the word that follows it in memory
+    pub fn decode_with_next(opcode: u16, next: u16) -> Option<Instruction> {
+        use Instruction::*;
+
+        let op_1 = (opcode & 0xF000) >> 12;
+        let op_2 = (opcode & 0x0F00) >> 8;
+        let op_3 = (opcode & 0x00F0) >> 4;
+        let op_4 = opcode & 0x000F;
+
+        let x = op_2 as u8;
+        let y = op_3 as u8;
+        let n = op_4 as u8;
+        let kk = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let instruction = match (op_1, op_2, op_3, op_4){
+            (0, 0, 0xE, 0) => Cls,
+            (0, 0, 0xE, 0xE) => Ret,
+            (0, 0, 0xC, _) => Scd(n),
+            (0, 0, 0xF, 0xB) => Scr,
+            (0, 0, 0xF, 0xC) => Scl,
+            (0, 0, 0xF, 0xD) => Exit,
+            (0, 0, 0xF, 0xE) => Low,
+            (0, 0, 0xF, 0xF) => High,
+            (0, _, _, _) => Sys(nnn),
+            (1, _, _, _) => Jp(nnn),
+            (2, _, _, _) => Call(nnn),
+            (3, _, _, _) => SeImm{x, kk},
+            (4, _, _, _) => SneImm{x, kk},
+            (5, _, _, 0) => SeReg{x, y},
+            (5, _, _, 2) => Save{x, y},
+            (5, _, _, 3) => Load{x, y},
+            (6, _, _, _) => LdImm{x, kk},
+            (7, _, _, _) => AddImm{x, kk},
+            (8, _, _, 0) => LdReg{x, y},
+            (8, _, _, 1) => Or{x, y},
+            (8, _, _, 2) => And{x, y},
+            (8, _, _, 3) => Xor{x, y},
+            (8, _, _, 4) => AddReg{x, y},
+            (8, _, _, 5) => Sub{x, y},
+            (8, _, _, 6) => Shr{x, y},
+            (8, _, _, 7) => Subn{x, y},
+            (8, _, _, 0xE) => Shl{x, y},
+            (9, _, _, 0) => SneReg{x, y},
+            (0xA, _, _, _) => LdI(nnn),
+            (0xB, _, _, _) => JpV0(nnn),
+            (0xC, _, _, _) => Rnd{x, kk},
+            (0xD, _, _, _) => Drw{x, y, n},
+            (0xE, _, 9, 0xE) => Skp{x},
+            (0xE, _, 0xA, 1) => Sknp{x},
+            (0xF, 0, 0, 0) => LdILong(next),
+            (0xF, _, 0, 1) => Plane(x),
+            (0xF, 0, 0, 2) => Audio,
+            (0xF, _, 0, 7) => LdVxDt{x},
+            (0xF, _, 0, 0xA) => LdVxK{x},
+            (0xF, _, 1, 5) => LdDtVx{x},
+            (0xF, _, 1, 8) => LdStVx{x},
+            (0xF, _, 1, 0xE) => AddI{x},
+            (0xF, _, 2, 9) => LdF{x},
+            (0xF, _, 3, 0) => LdHf{x},
+            (0xF, _, 3, 3) => LdB{x},
+            (0xF, _, 3, 0xA) => Pitch{x},
+            (0xF, _, 5, 5) => LdIVx{x},
+            (0xF, _, 6, 5) => LdVxI{x},
+            (0xF, _, 7, 5) => LdRVx{x},
+            (0xF, _, 8, 5) => LdVxR{x},
+            _ => return None
+        };
+        Some(instruction)
+    }
+
+    //the first word of the instruction
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |op: u16, x: u8, y: u8, n: u16| (op << 12) | ((x as u16) << 8) | ((y as u16) << 4) | n;
+        let xkk = |op: u16, x: u8, kk: u8| (op << 12) | ((x as u16) << 8) | kk as u16;
+        let fx = |x: u8, kk: u16| 0xF000 | ((x as u16) << 8) | kk;
+
+        match *self {
+            Sys(nnn) => nnn & 0x0FFF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Scd(n) => 0x00C0 | (n as u16 & 0xF),
+            Scr => 0x00FB,
+            Scl => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jp(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            SeImm{x, kk} => xkk(3, x, kk),
+            SneImm{x, kk} => xkk(4, x, kk),
+            SeReg{x, y} => xy(5, x, y, 0),
+            Save{x, y} => xy(5, x, y, 2),
+            Load{x, y} => xy(5, x, y, 3),
+            LdImm{x, kk} => xkk(6, x, kk),
+            AddImm{x, kk} => xkk(7, x, kk),
+            LdReg{x, y} => xy(8, x, y, 0),
+            Or{x, y} => xy(8, x, y, 1),
+            And{x, y} => xy(8, x, y, 2),
+            Xor{x, y} => xy(8, x, y, 3),
+            AddReg{x, y} => xy(8, x, y, 4),
+            Sub{x, y} => xy(8, x, y, 5),
+            Shr{x, y} => xy(8, x, y, 6),
+            Subn{x, y} => xy(8, x, y, 7),
+            Shl{x, y} => xy(8, x, y, 0xE),
+            SneReg{x, y} => xy(9, x, y, 0),
+            LdI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            JpV0(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Rnd{x, kk} => xkk(0xC, x, kk),
+            Drw{x, y, n} => xy(0xD, x, y, n as u16 & 0xF),
+            Skp{x} => xkk(0xE, x, 0x9E),
+            Sknp{x} => xkk(0xE, x, 0xA1),
+            LdILong(_) => 0xF000,
+            Plane(n) => fx(n, 0x01),
+            Audio => 0xF002,
+            LdVxDt{x} => fx(x, 0x07),
+            LdVxK{x} => fx(x, 0x0A),
+            LdDtVx{x} => fx(x, 0x15),
+            LdStVx{x} => fx(x, 0x18),
+            AddI{x} => fx(x, 0x1E),
+            LdF{x} => fx(x, 0x29),
+            LdHf{x} => fx(x, 0x30),
+            LdB{x} => fx(x, 0x33),
+            Pitch{x} => fx(x, 0x3A),
+            LdIVx{x} => fx(x, 0x55),
+            LdVxI{x} => fx(x, 0x65),
+            LdRVx{x} => fx(x, 0x75),
+            LdVxR{x} => fx(x, 0x85)
+        }
+    }
+
+    //length of the instruction in bytes
+    pub fn length(&self) -> u16 {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2
+        }
+    }
+
+    //the instruction as it is laid out in memory
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LdILong(address) = self {
+            bytes.extend_from_slice(&address.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scd(n) => write!(f, "SCD {}", n),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            SeImm{x, kk} => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            SneImm{x, kk} => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            SeReg{x, y} => write!(f, "SE V{:X}, V{:X}", x, y),
+            Save{x, y} => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Load{x, y} => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            LdImm{x, kk} => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            AddImm{x, kk} => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            LdReg{x, y} => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or{x, y} => write!(f, "OR V{:X}, V{:X}", x, y),
+            And{x, y} => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor{x, y} => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg{x, y} => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub{x, y} => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr{x, y} => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn{x, y} => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl{x, y} => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg{x, y} => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Rnd{x, kk} => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Drw{x, y, n} => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp{x} => write!(f, "SKP V{:X}", x),
+            Sknp{x} => write!(f, "SKNP V{:X}", x),
+            LdILong(address) => write!(f, "LD I, LONG 0x{:04X}", address),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            LdVxDt{x} => write!(f, "LD V{:X}, DT", x),
+            LdVxK{x} => write!(f, "LD V{:X}, K", x),
+            LdDtVx{x} => write!(f, "LD DT, V{:X}", x),
+            LdStVx{x} => write!(f, "LD ST, V{:X}", x),
+            AddI{x} => write!(f, "ADD I, V{:X}", x),
+            LdF{x} => write!(f, "LD F, V{:X}", x),
+            LdHf{x} => write!(f, "LD HF, V{:X}", x),
+            LdB{x} => write!(f, "LD B, V{:X}", x),
+            Pitch{x} => write!(f, "PITCH V{:X}", x),
+            LdIVx{x} => write!(f, "LD [I], V{:X}", x),
+            LdVxI{x} => write!(f, "LD V{:X}, [I]", x),
+            LdRVx{x} => write!(f, "LD R, V{:X}", x),
+            LdVxR{x} => write!(f, "LD V{:X}, R", x)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_test() {
+        assert_eq!(Some(Instruction::Cls), Instruction::decode(0x00E0));
+        assert_eq!(Some(Instruction::Jp(0x2A4)), Instruction::decode(0x12A4));
+        assert_eq!(Some(Instruction::Drw{x: 1, y: 2, n: 5}), Instruction::decode(0xD125));
+        assert_eq!(Some(Instruction::LdILong(0xBEEF)), Instruction::decode_with_next(0xF000, 0xBEEF));
+        assert_eq!(None, Instruction::decode(0x5121));
+        assert_eq!(None, Instruction::decode(0xE1FF));
+    }
+
+    #[test]
+    fn encode_round_trip_test() {
+        for opcode in 0..=0xFFFF{
+            if let Some(instruction) = Instruction::decode(opcode){
+                assert_eq!(opcode, instruction.encode());
+            }
+        }
+    }
+
+    #[test]
+    fn to_bytes_test() {
+        assert_eq!(vec![0x8B, 0xC4], Instruction::AddReg{x: 0xB, y: 0xC}.to_bytes());
+        assert_eq!(vec![0xF0, 0x00, 0x12, 0x34], Instruction::LdILong(0x1234).to_bytes());
+    }
+
+    #[test]
+    fn display_test() {
+        assert_eq!("CLS", Instruction::Cls.to_string());
+        assert_eq!("DRW V1, V2, 5", Instruction::Drw{x: 1, y: 2, n: 5}.to_string());
+        assert_eq!("LD VA, 0x22", Instruction::LdImm{x: 0xA, kk: 0x22}.to_string());
+        assert_eq!("JP V0, 0xCCC", Instruction::JpV0(0xCCC).to_string());
+        assert_eq!("LD [I], VF", Instruction::LdIVx{x: 0xF}.to_string());
+        assert_eq!("SAVE V2 - V4", Instruction::Save{x: 2, y: 4}.to_string());
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+//just enough JSON for the debug adapter protocol
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>)
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.chars.len() {
+            return Err(format!("Unexpected '{}' after the value", parser.chars[parser.position]));
+        }
+        Ok(value)
+    }
+
+    //builds an object from key and value pairs
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    //looks a key up in an object, anything else has no keys
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.get(key),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(number) if number.fract() == 0.0 => Some(*number as i64),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Json {
+        Json::Array(values)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+//compact output, integers are written without a fraction
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => write!(f, "{}", *number as i64),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.chars.len() && self.chars[self.position].is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = *self.chars.get(self.position).ok_or("Unexpected end of JSON")?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(format!("Expected '{}'", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.position) {
+            Some('n') => self.expect_word("null", Json::Null),
+            Some('t') => self.expect_word("true", Json::Bool(true)),
+            Some('f') => self.expect_word("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+            None => Err(String::from("Unexpected end of JSON"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self.position < self.chars.len() && "+-0123456789.eE".contains(self.chars[self.position]) {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse().map(Json::Number).map_err(|_| format!("Invalid number '{}'", text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.next()?;
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(text),
+                '\\' => match self.next()? {
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let hex: String = (0..4).map(|_| self.next()).collect::<Result<String, String>>()?;
+                        let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape '\\u{}'", hex))?;
+                        //surrogate pairs are not needed for paths and expressions, so they become U+FFFD
+                        text.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    c => text.push(c)
+                },
+                c => text.push(c)
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.next()?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.chars.get(self.position) == Some(&']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => {}
+                ']' => return Ok(Json::Array(values)),
+                c => return Err(format!("Expected ',' or ']' but found '{}'", c))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.next()?;
+        let mut fields = BTreeMap::new();
+        self.skip_whitespace();
+        if self.chars.get(self.position) == Some(&'}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.get(self.position) != Some(&'"') {
+                return Err(String::from("Expected a key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.next()? != ':' {
+                return Err(String::from("Expected ':'"));
+            }
+            fields.insert(key, self.value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => {}
+                '}' => return Ok(Json::Object(fields)),
+                c => return Err(format!("Expected ',' or '}}' but found '{}'", c))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let json = Json::parse(r#" {"seq": 3, "arguments": {"lines": [1, 20], "path": "C:\\roms\\\"pong\"", "stop": true}, "x": null} "#).unwrap();
+        assert_eq!(Some(3), json.get("seq").and_then(Json::as_i64));
+        let arguments = json.get("arguments").unwrap();
+        assert_eq!(Some(r#"C:\roms\"pong""#), arguments.get("path").and_then(Json::as_str));
+        assert_eq!(2, arguments.get("lines").and_then(Json::as_array).unwrap().len());
+        assert_eq!(Some(true), arguments.get("stop").and_then(Json::as_bool));
+        assert_eq!(Some(&Json::Null), json.get("x"));
+        assert_eq!(Json::String(String::from("é\n")), Json::parse(r#""\u00e9\n""#).unwrap());
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("tru").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn display_test() {
+        let json = Json::object(vec![
+            ("name", Json::from("V0 \"x\"")),
+            ("value", Json::from(16)),
+            ("half", Json::Number(0.5)),
+            ("list", Json::from(vec![Json::from(true), Json::Null]))
+        ]);
+        let text = json.to_string();
+        assert_eq!(r#"{"half":0.5,"list":[true,null],"name":"V0 \"x\"","value":16}"#, text);
+        assert_eq!(json, Json::parse(&text).unwrap());
+    }
+}
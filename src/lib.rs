@@ -0,0 +1,33 @@
+//the original tests compare booleans with assert_eq! and keep a few unneeded muts
+#![cfg_attr(test, allow(unused_mut, clippy::bool_assert_comparison))]
+
+pub mod ram;
+pub mod cpu;
+pub mod byte_register;
+pub mod word_register;
+pub mod graphics_buffer;
+#[cfg(feature = "sdl")]
+pub mod speaker;
+pub mod quirks;
+pub mod rng;
+pub mod instruction;
+pub mod disassembler;
+pub mod assembler;
+pub mod error;
+pub mod machine;
+pub mod save_state;
+pub mod rewind;
+pub mod movie;
+pub mod scheduler;
+pub mod playback;
+pub mod timing;
+pub mod idle;
+mod breakpoints;
+pub mod debugger;
+pub mod expression;
+pub mod remote;
+pub mod gdb;
+pub mod json;
+pub mod dap;
+
+pub use machine::Machine;
@@ -0,0 +1,520 @@
+use crate::cpu::CPU;
+use crate::error::EmulatorError;
+use crate::graphics_buffer::GraphicsBuffer;
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::ram::RAM;
+use crate::rng::Xorshift;
+use crate::save_state::{StateReader, StateWriter};
+use crate::timing::VIP_CYCLES_PER_FRAME;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+
+//the CPU, memory and framebuffer wired together, ready to run a ROM
+pub struct Machine {
+    cpu: CPU,
+    gfx: GraphicsBuffer,
+    instructions_per_frame: usize,
+    //frames spend VIP machine cycles instead of a flat instruction count
+    vip_timing: bool,
+    //busy waits skip the passes that cannot change anything, off while debugging so every pass is seen
+    idle_skip: bool,
+    //what is left of the current frame in instructions or cycles, negative if the last instruction overran it
+    frame_budget: i64
+}
+
+impl Machine {
+    pub fn new(quirks: Quirks) -> Machine {
+        Machine {
+            cpu: CPU::new(RAM::new(), quirks),
+            gfx: GraphicsBuffer::new(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            vip_timing: false,
+            idle_skip: true,
+            frame_budget: 0
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        self.cpu.ram_mut().load_bytes(rom)
+    }
+
+    //executes a single instruction, returning the VIP machine cycles it took
+    pub fn step(&mut self) -> Result<u32, EmulatorError> {
+        let in_frame = self.frame_budget > 0;
+        let cycles = self.cpu.cycle(&mut self.gfx)?;
+        //steps a debugger takes after the frame ran out must not eat into the frames that follow
+        if in_frame {
+            self.frame_budget -= if self.vip_timing {cycles as i64} else {1};
+        }
+        //skip whole passes of a busy wait, leaving the last so the frame still ends on the same instruction
+        if let Some(idle) = self.cpu.take_idle_loop().filter(|_| self.idle_skip) {
+            let pass = if self.vip_timing {idle.cycles} else {idle.instructions} as i64;
+            if pass > 0 && self.frame_budget > pass {
+                self.frame_budget -= (self.frame_budget - 1) / pass * pass;
+            }
+        }
+        Ok(cycles)
+    }
+
+    //runs one 60 Hz frame worth of instructions, then ticks the timers
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        self.begin_frame();
+        while self.frame_pending() {
+            self.step()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    //starts a frame's budget, frontends that check each instruction use this with frame_pending instead of run_frame
+    //with VIP timing, cycles an instruction ran over the last frame by are taken out of this one
+    pub fn begin_frame(&mut self) {
+        self.frame_budget = if self.vip_timing {
+            self.frame_budget.min(0) + VIP_CYCLES_PER_FRAME as i64
+        } else {
+            self.instructions_per_frame as i64
+        };
+    }
+
+    //whether the current frame has room for another instruction
+    pub fn frame_pending(&self) -> bool {
+        self.frame_budget > 0 && !self.cpu.exit_flag && !self.cpu.display_waiting()
+    }
+
+    //the end of a frame, which is also the vertical blank
+    pub fn tick_timers(&mut self) {
+        self.cpu.timer();
+        self.cpu.vblank();
+    }
+
+    //true if a single step from the address left a draw stalled by the display wait quirk, which is then released
+    //no frame ends while a debugger steps, so the vertical blank it waits for might never come
+    pub fn stalled_draw(&mut self, from: u16) -> bool {
+        let pc = self.cpu.program_counter().read_reg();
+        let draw = matches!(Instruction::decode(self.cpu.ram().read_word(pc)), Some(Instruction::Drw{..}));
+        if pc != from || !draw || !self.cpu.quirks().display_wait {
+            return false;
+        }
+        if self.cpu.display_waiting() {
+            self.cpu.vblank();
+        }
+        true
+    }
+
+    //makes CXNN produce the same sequence on every run
+    pub fn seed(&mut self, seed: u64) {
+        self.cpu.replace_rng(Box::new(Xorshift::new(seed)));
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: usize) {
+        self.instructions_per_frame = instructions;
+    }
+
+    pub fn vip_timing(&self) -> bool {
+        self.vip_timing
+    }
+
+    pub fn set_vip_timing(&mut self, vip_timing: bool) {
+        self.vip_timing = vip_timing;
+        self.frame_budget = 0;
+    }
+
+    //the machine ends each frame the same either way, only the instructions run on the way there differ
+    pub fn set_idle_skip(&mut self, idle_skip: bool) {
+        self.idle_skip = idle_skip;
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.cpu.keys_pressed[(key & 0xF) as usize] = pressed;
+        self.cpu.reset_idle();
+    }
+
+    pub fn framebuffer(&self) -> &GraphicsBuffer {
+        &self.gfx
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.cpu.sound_flag
+    }
+
+    //true once the ROM has executed 00FD
+    pub fn exited(&self) -> bool {
+        self.cpu.exit_flag
+    }
+
+    //whether the framebuffer changed since the last call
+    pub fn take_draw_flag(&mut self) -> bool {
+        let draw = self.cpu.draw_flag;
+        self.cpu.draw_flag = false;
+        draw
+    }
+
+    //whether the audio pattern or pitch changed since the last call
+    pub fn take_audio_flag(&mut self) -> bool {
+        let audio = self.cpu.audio_flag;
+        self.cpu.audio_flag = false;
+        audio
+    }
+
+    //a snapshot of everything the ROM can observe, see save_state.rs for the header
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        self.gfx.save_state(&mut writer);
+        writer.finish()
+    }
+
+    //leaves the machine untouched if the state is invalid
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state)?;
+        let mut cpu = CPU::load_state(&mut reader)?;
+        let gfx = GraphicsBuffer::load_state(&mut reader)?;
+        reader.finish()?;
+        //keep whatever random source was injected, just at the saved position
+        let rng_state = cpu.rng().state();
+        cpu.replace_rng(self.cpu.replace_rng(Box::new(Xorshift::new(0))));
+        cpu.rng_mut().set_state(rng_state);
+        //watchpoints are debugger settings rather than machine state, so they survive a load
+        for watchpoint in self.cpu.ram().watchpoints() {
+            cpu.ram_mut().add_watchpoint(*watchpoint);
+        }
+        self.cpu = cpu;
+        self.gfx = gfx;
+        //an overrun belongs to the frame that was running, not the one loaded
+        self.frame_budget = 0;
+        Ok(())
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new(Quirks::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn machine(source: &str) -> Machine {
+        let mut machine = Machine::default();
+        machine.load_rom(&assemble(source).unwrap()).unwrap();
+        machine
+    }
+
+    #[test]
+    fn step_test() {
+        let mut machine = machine("LD V0, 0x05\nLD F, V0\nDRW V1, V1, 5");
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert!(machine.framebuffer().get(0, 0));
+        assert!(!machine.framebuffer().get(3, 1));
+        assert!(machine.take_draw_flag());
+        assert!(!machine.take_draw_flag());
+    }
+
+    #[test]
+    fn run_frame_test() {
+        let mut machine = machine("LD V0, 3\nLD ST, V0\nLD I, 0x300\nloop: ADD V1, 1\nLD [I], V1\nJP loop");
+        machine.set_instructions_per_frame(6);
+        machine.run_frame().unwrap();
+        assert!(machine.sound_active());
+        assert_eq!(1, machine.cpu().ram().read_byte(0x301));
+        machine.run_frame().unwrap();
+        assert_eq!(3, machine.cpu().ram().read_byte(0x301));
+    }
+
+    #[test]
+    fn exit_test() {
+        let mut machine = machine("EXIT\nLD V0, 1");
+        machine.run_frame().unwrap();
+        assert!(machine.exited());
+    }
+
+    #[test]
+    fn set_key_test() {
+        let mut machine = machine("LD V0, 0xA\nSKP V0\nEXIT\nLD V1, 1");
+        machine.set_key(0xA, true);
+        machine.run_frame().unwrap();
+        assert!(!machine.exited());
+    }
+
+    #[test]
+    fn error_test() {
+        let mut machine = machine("RET");
+        assert!(machine.run_frame().is_err());
+    }
+
+    #[test]
+    fn save_state_test() {
+        let mut machine = machine("HIGH\nPLANE 3\nLD V2, 9\nLD DT, V2\nCALL sub\nEXIT\nsub: LD F, V2\nDRW V3, V3, 5\nloop: ADD V1, 1\nJP loop");
+        machine.set_key(4, true);
+        for _ in 0..10 {
+            machine.step().unwrap();
+        }
+        machine.tick_timers();
+        let state = machine.save_state();
+        assert_eq!(b"F8ST", &state[..4]);
+
+        let mut restored = Machine::new(Quirks::vip());
+        restored.load_state(&state).unwrap();
+        assert_eq!(state, restored.save_state());
+        assert!(restored.take_draw_flag());
+        assert!(restored.take_audio_flag());
+        assert_eq!(1, restored.cpu().stack().len());
+        assert_eq!(8, restored.cpu().delay_timer().read_reg());
+        assert!(restored.framebuffer().is_hires());
+        assert_eq!(3, restored.framebuffer().selected_planes());
+        assert!(restored.cpu().keys_pressed[4]);
+        assert_eq!(Quirks::default(), restored.cpu().quirks());
+
+        for _ in 0..20 {
+            machine.step().unwrap();
+            restored.step().unwrap();
+        }
+        assert_eq!(machine.save_state(), restored.save_state());
+    }
+
+    #[test]
+    fn invalid_state_test() {
+        let mut machine = machine("LD V0, 7");
+        machine.step().unwrap();
+        let state = machine.save_state();
+        let mut other = Machine::default();
+        let before = other.save_state();
+        assert!(other.load_state(&state[..state.len() - 1]).is_err());
+        let mut extended = state.clone();
+        extended.push(0);
+        assert!(other.load_state(&extended).is_err());
+        let mut newer = state.clone();
+        newer[5] += 1;
+        assert!(other.load_state(&newer).is_err());
+        assert!(other.load_state(b"not a state").is_err());
+        assert_eq!(before, other.save_state());
+    }
+
+    fn random_bytes(machine: &mut Machine) -> Vec<u8> {
+        (0..8).map(|_| {
+            machine.step().unwrap();
+            machine.cpu().v()[0].read_reg()
+        }).collect()
+    }
+
+    #[test]
+    fn seed_test() {
+        let source = "loop: RND V0, 0xFF\nJP loop";
+        let mut a = machine(source);
+        let mut b = machine(source);
+        a.seed(7);
+        b.seed(7);
+        let sequence = random_bytes(&mut a);
+        assert_eq!(sequence, random_bytes(&mut b));
+
+        //the random source is part of the save state
+        let state = a.save_state();
+        let expected = random_bytes(&mut a);
+        b.seed(99);
+        b.load_state(&state).unwrap();
+        assert_eq!(expected, random_bytes(&mut b));
+    }
+
+    #[test]
+    fn old_version_state_test() {
+        let mut machine = machine("LD V4, 0x44");
+        machine.step().unwrap();
+        //version 3 had no extended memory quirk, version 2 no display wait quirk or vblank flags
+        //and version 1 no random state either
+        for (version, removed) in [(3, 0..0), (2, 143..145), (1, 135..145)] {
+            let mut state = machine.save_state();
+            state[5] = version;
+            state.drain(removed);
+            state.remove(12);
+            if version < 3 {
+                state.remove(11);
+            }
+            let mut restored = Machine::new(Quirks::vip());
+            restored.load_state(&state).unwrap();
+            assert_eq!(0x44, restored.cpu().v()[4].read_reg());
+            assert_eq!(0x202, restored.cpu().program_counter().read_reg());
+            assert_eq!(Quirks::default(), restored.cpu().quirks());
+        }
+    }
+
+    #[test]
+    fn vip_timing_test() {
+        //a loop of cheap instructions runs far more often per frame than a loop of screen clears
+        let mut fast = machine("loop: ADD V0, 1\nJP loop");
+        fast.set_vip_timing(true);
+        fast.run_frame().unwrap();
+        //ADD and JP cost 102 cycles a loop, so the 3668 cycle frame fits 36 of them
+        let cheap = fast.cpu().v()[0].read_reg();
+        assert_eq!(36, cheap);
+
+        let mut slow = machine("loop: ADD V0, 1\nCLS\nJP loop");
+        slow.set_vip_timing(true);
+        slow.run_frame().unwrap();
+        let clears = slow.cpu().v()[0].read_reg();
+        assert!(clears >= 1 && clears < cheap / 10);
+    }
+
+    #[test]
+    fn overrun_test() {
+        //a clear fits in a frame with room to start another, and what that overruns comes out of the next frame
+        let mut machine = machine("CLS\nCLS\nCLS\nEXIT");
+        machine.set_vip_timing(true);
+        for pc in [0x204, 0x206, 0x208] {
+            machine.run_frame().unwrap();
+            assert_eq!(pc, machine.cpu().program_counter().read_reg());
+        }
+        machine.run_frame().unwrap();
+        assert!(machine.exited());
+    }
+
+    #[test]
+    fn budget_reset_test() {
+        //debugger steps beyond the end of a frame leave the next frame its full budget
+        let mut stepped = machine("loop: ADD V0, 1\nJP loop");
+        stepped.set_vip_timing(true);
+        stepped.run_frame().unwrap();
+        for _ in 0..1000 {
+            stepped.step().unwrap();
+        }
+        let before = stepped.cpu().v()[0].read_reg();
+        stepped.run_frame().unwrap();
+        assert!(stepped.cpu().v()[0].read_reg().wrapping_sub(before) >= 35);
+
+        //and loading a state drops the overrun of the frame that was running
+        let mut loaded = machine("CLS\nCLS\nCLS\nEXIT");
+        loaded.set_vip_timing(true);
+        let state = loaded.save_state();
+        loaded.run_frame().unwrap();
+        loaded.load_state(&state).unwrap();
+        loaded.run_frame().unwrap();
+        assert_eq!(0x204, loaded.cpu().program_counter().read_reg());
+    }
+
+    #[test]
+    fn display_wait_test() {
+        //with the VIP quirks each frame draws one sprite, however many instructions it may run
+        let mut machine = Machine::new(Quirks::vip());
+        machine.load_rom(&assemble("loop: DRW V0, V0, 1\nADD V1, 1\nJP loop").unwrap()).unwrap();
+        machine.set_instructions_per_frame(100);
+        machine.run_frame().unwrap();
+        assert_eq!(0, machine.cpu().v()[1].read_reg());
+        for frames in 1..4 {
+            machine.run_frame().unwrap();
+            assert_eq!(frames, machine.cpu().v()[1].read_reg());
+        }
+    }
+
+    #[test]
+    fn stalled_draw_test() {
+        //no vertical blank has happened yet, so stepping the draw stalls until it is released
+        let mut machine = Machine::new(Quirks::vip());
+        machine.load_rom(&assemble("DRW V0, V0, 1\nEXIT").unwrap()).unwrap();
+        machine.step().unwrap();
+        assert!(machine.cpu().display_waiting());
+        assert!(machine.stalled_draw(0x200));
+        assert!(!machine.cpu().display_waiting());
+        machine.step().unwrap();
+        assert_eq!(0x202, machine.cpu().program_counter().read_reg());
+        assert!(machine.take_draw_flag());
+        assert!(!machine.stalled_draw(0x200));
+    }
+
+    #[test]
+    fn idle_skip_off_test() {
+        //with skipping off every pass of a busy wait runs, so a debugger sees each one
+        let rom = assemble("LD V0, 5\nLD DT, V0\nwait: LD V1, DT\nSE V1, 0\nJP wait").unwrap();
+        let mut skipping = Machine::default();
+        skipping.load_rom(&rom).unwrap();
+        skipping.set_instructions_per_frame(1000);
+        skipping.seed(1);
+        let mut stepping = Machine::default();
+        stepping.load_rom(&rom).unwrap();
+        stepping.set_instructions_per_frame(1000);
+        stepping.set_idle_skip(false);
+        stepping.seed(1);
+        assert!(counted_frame(&mut skipping) < 20);
+        assert_eq!(1000, counted_frame(&mut stepping));
+        assert_eq!(skipping.save_state(), stepping.save_state());
+    }
+
+    //runs a frame one instruction at a time with no idle skipping, returning how many ran
+    fn reference_frame(machine: &mut Machine) -> u32 {
+        machine.begin_frame();
+        let mut instructions = 0;
+        while machine.frame_pending() {
+            let cycles = machine.cpu.cycle(&mut machine.gfx).unwrap();
+            machine.cpu.take_idle_loop();
+            machine.frame_budget -= if machine.vip_timing {cycles as i64} else {1};
+            instructions += 1;
+        }
+        machine.tick_timers();
+        instructions
+    }
+
+    fn counted_frame(machine: &mut Machine) -> u32 {
+        machine.begin_frame();
+        let mut instructions = 0;
+        while machine.frame_pending() {
+            machine.step().unwrap();
+            instructions += 1;
+        }
+        machine.tick_timers();
+        instructions
+    }
+
+    #[test]
+    fn idle_loop_test() {
+        //waits out the delay timer, then counts frames in V2 and waits for a key
+        let rom = assemble("LD V0, 5\nLD DT, V0\nwait: LD V1, DT\nSE V1, 0\nJP wait\nADD V2, 1\nLD V3, K\nLD V0, 2\nLD DT, V0\nJP wait").unwrap();
+        for &vip_timing in [false, true].iter() {
+            let mut skipping = Machine::default();
+            skipping.load_rom(&rom).unwrap();
+            skipping.set_instructions_per_frame(1000);
+            skipping.set_vip_timing(vip_timing);
+            skipping.seed(1);
+            let mut reference = Machine::default();
+            reference.load_rom(&rom).unwrap();
+            reference.set_instructions_per_frame(1000);
+            reference.set_vip_timing(vip_timing);
+            reference.seed(1);
+            for frame in 0..20 {
+                skipping.set_key(4, frame == 10);
+                reference.set_key(4, frame == 10);
+                let skipped = counted_frame(&mut skipping);
+                let run = reference_frame(&mut reference);
+                assert!(skipped < 20, "frame {} ran {} instructions", frame, skipped);
+                assert!(run > 60);
+                assert_eq!(reference.save_state(), skipping.save_state());
+            }
+            assert_eq!(2, skipping.cpu().v()[2].read_reg());
+        }
+    }
+
+    #[test]
+    fn busy_loop_test() {
+        //a loop that changes a register each pass is not idle and runs in full
+        let mut machine = Machine::default();
+        machine.load_rom(&assemble("loop: ADD V0, 1\nJP loop").unwrap()).unwrap();
+        machine.set_instructions_per_frame(100);
+        assert_eq!(100, counted_frame(&mut machine));
+        assert_eq!(50, machine.cpu().v()[0].read_reg());
+    }
+}
@@ -1,34 +1,179 @@
 extern crate sdl2;
-use crate::ram::RAM;
-use crate::cpu::CPU;
-use crate::graphics_buffer::GraphicsBuffer;
-use crate::speaker::Speaker;
+use ferric_8::Machine;
+use ferric_8::debugger::Debugger;
+use ferric_8::gdb::GdbStub;
+use ferric_8::dap::DapServer;
+use ferric_8::remote::RemoteDebugger;
+use ferric_8::graphics_buffer::Palette;
+use ferric_8::graphics_buffer::{DEFAULT_PALETTE, parse_palette};
+use ferric_8::speaker::Speaker;
+use ferric_8::quirks::Quirks;
+use ferric_8::movie::{Movie, Player, Recorder};
+use ferric_8::playback::Playback;
+use ferric_8::scheduler::{FrameScheduler, FRAME_RATE};
+use ferric_8::rewind::{Rewind, DEFAULT_INTERVAL, DEFAULT_MEMORY_LIMIT};
 
-use std::thread::sleep;
-use std::time::{Duration, SystemTime};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::render::WindowCanvas;
 use std::env::args;
-
-mod ram;
-mod cpu;
-mod byte_register;
-mod word_register;
-mod graphics_buffer;
-mod speaker;
+use std::fs::{read, write};
+use std::path::{Path, PathBuf};
+use std::io::{stdin, stdout, Write};
+use std::process::exit;
 
 const PIXEL_WIDTH: u32 = 16;
 const HEIGHT: u32 = 32*PIXEL_WIDTH;
 const WIDTH: u32 = 64*PIXEL_WIDTH;
 
 fn main() {
-    let args: Vec<String> = args().collect();
-    let filename = &args[1];
-    // backend code
-    let mut ram = RAM::new();
-    ram.load_rom(String::from(filename));
-    let mut cpu = CPU::new(ram);
-    let mut gfx = GraphicsBuffer::new();
+    let mut filename = None;
+    let mut quirks = Quirks::default();
+    let mut palette = DEFAULT_PALETTE;
+    let mut debugger = None;
+    let mut gdb_port = None;
+    let mut dap = None;
+    let mut seed = None;
+    let mut ipf = None;
+    let mut vip_timing = false;
+    let mut fast_forward = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut rewind_memory = DEFAULT_MEMORY_LIMIT;
+    let mut rewind_interval = DEFAULT_INTERVAL;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().expect("--quirks needs a profile name");
+                quirks = Quirks::from_name(&name)
+                    .expect("Unknown quirks profile, expected vip, chip48, schip or xochip");
+            }
+            "--palette" => {
+                let colours = args.next().expect("--palette needs four colours");
+                palette = parse_palette(&colours)
+                    .expect("Palette should be four hex colours, e.g. 000000,ffffff,aaaaaa,555555");
+            }
+            "--debug" => debugger = Some(Debugger::new()),
+            "--gdb" => {
+                let port = args.next().expect("--gdb needs a port");
+                gdb_port = Some(port.parse::<u16>().expect("--gdb port should be a number"));
+            }
+            //the editor sends the ROM path in its launch request
+            "--dap" => dap = Some(DapServer::stdio()),
+            "--dap-port" => {
+                let port = args.next().expect("--dap-port needs a port");
+                let port = port.parse::<u16>().expect("--dap-port port should be a number");
+                dap = Some(DapServer::listen(port).unwrap_or_else(|error| {
+                    eprintln!("Could not serve the debug adapter on port {}: {}", port, error);
+                    exit(1);
+                }));
+            }
+            "--seed" => {
+                let value = args.next().expect("--seed needs a number");
+                seed = Some(value.parse::<u64>().expect("--seed should be a number"));
+            }
+            "--record" => record_path = Some(args.next().expect("--record needs a movie path")),
+            "--play" => play_path = Some(args.next().expect("--play needs a movie path")),
+            "--ipf" => {
+                let instructions = args.next().expect("--ipf needs a number of instructions");
+                ipf = Some(instructions.parse::<usize>().expect("--ipf should be a number of instructions"));
+            }
+            "--vip-timing" => vip_timing = true,
+            //0 leaves fast forward uncapped
+            "--fast-forward" => {
+                let speed = args.next().expect("--fast-forward needs a speed");
+                let speed = speed.parse::<f64>().expect("--fast-forward should be a number");
+                fast_forward = if speed > 0.0 {Some(speed)} else {None};
+            }
+            "--rewind-memory" => {
+                let megabytes = args.next().expect("--rewind-memory needs a size in MiB");
+                rewind_memory = megabytes.parse::<usize>().expect("--rewind-memory should be a number of MiB") * 1024 * 1024;
+            }
+            "--rewind-interval" => {
+                let frames = args.next().expect("--rewind-interval needs a number of frames");
+                rewind_interval = frames.parse::<usize>().expect("--rewind-interval should be a number of frames");
+            }
+            _ => filename = Some(arg)
+        }
+    }
+    let mut rom = Vec::new();
+    let mut machine = match dap.as_mut(){
+        Some(server) => match server.launch(quirks){
+            Ok(Some(machine)) => machine,
+            Ok(None) => exit(0),
+            Err(error) => {
+                eprintln!("Lost connection to the debug adapter client: {}", error);
+                exit(1);
+            }
+        },
+        None => {
+            let filename = filename.clone().expect("Usage: ferric-8 [--quirks vip|chip48|schip|xochip] [--palette c0,c1,c2,c3] [--debug] [--gdb port] [--dap] [--dap-port port] [--ipf n | --vip-timing] [--fast-forward speed] [--seed n] [--record movie.c8m | --play movie.c8m] [--rewind-memory MiB] [--rewind-interval frames] path/to/rom.ch8");
+            // backend code
+            rom = match read(&filename){
+                Ok(rom) => rom,
+                Err(error) => {
+                    eprintln!("Could not load {}: {}", filename, error);
+                    exit(1);
+                }
+            };
+            let mut machine = Machine::new(quirks);
+            if let Err(error) = machine.load_rom(&rom){
+                eprintln!("Could not load {}: {}", filename, error);
+                exit(1);
+            }
+            machine
+        }
+    };
+    if let Some(seed) = seed{
+        machine.seed(seed);
+    }
+    if let Some(ipf) = ipf{
+        machine.set_instructions_per_frame(ipf);
+    }
+    machine.set_vip_timing(vip_timing);
+
+    //movies run whole frames, so every run executes the same instructions between inputs
+    let mut recorder = None;
+    let mut player = None;
+    if record_path.is_some() && play_path.is_some(){
+        eprintln!("--record and --play cannot be used together");
+        exit(1);
+    }
+    if (record_path.is_some() || play_path.is_some()) && rom.is_empty(){
+        eprintln!("--record and --play need a ROM path on the command line");
+        exit(1);
+    }
+    if let Some(path) = play_path.as_ref(){
+        let movie = match read(path){
+            Ok(bytes) => Movie::parse(&bytes),
+            Err(error) => Err(error.into())
+        };
+        let movie = movie.unwrap_or_else(|error| {
+            eprintln!("Could not load {}: {}", path, error);
+            exit(1);
+        });
+        //the movie decides the quirks, seed and timing
+        machine = movie.machine(&rom).unwrap_or_else(|error| {
+            eprintln!("Could not play {}: {}", path, error);
+            exit(1);
+        });
+        player = Some(Player::new(movie));
+    } else if record_path.is_some(){
+        //a movie needs a known seed to be reproducible, so pick one if none was given
+        recorder = Some(Recorder::start(&rom, seed.unwrap_or_else(rand::random), &mut machine));
+    }
+    let movie_active = recorder.is_some() || player.is_some();
+    //debuggers get to see every pass of a busy wait, so tracepoints and read watchpoints in it still fire
+    machine.set_idle_skip(debugger.is_none() && gdb_port.is_none() && dap.is_none());
+
+    let mut gdb = gdb_port.map(|port| {
+        eprintln!("Waiting for gdb to connect to 127.0.0.1:{}", port);
+        GdbStub::listen(port).unwrap_or_else(|error| {
+            eprintln!("Could not serve gdb on port {}: {}", port, error);
+            exit(1);
+        })
+    });
 
     //sdl2 code
     let sdl = sdl2::init().expect("Could not initalize sdl");
@@ -37,70 +182,285 @@ fn main() {
     .position_centered().build().expect("Could not initialize window");
     let mut canvas = window.into_canvas().build().expect("Could not create canvas");
     let mut event_pump = sdl.event_pump().expect("Could not initliaze event handler");
-    let mut last_tick_time = SystemTime::now();
     let mut speaker = Speaker::new(sdl);
+    let mut rewind = Rewind::new(rewind_memory, rewind_interval);
+    let mut rewinding = false;
+    let mut scheduler = FrameScheduler::new(FRAME_RATE);
+    let mut playback = Playback::new(fast_forward);
+    let mut speed = playback.speed();
+    let mut title = String::new();
 
-    //main loop
-    loop {
+    //main loop, one iteration per 60 Hz frame
+    'main: loop {
         //input
         for event in event_pump.poll_iter() {
             match event {
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => { break },
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => { cpu.keys_pressed[1] = true },
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } => { cpu.keys_pressed[2] = true },
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { cpu.keys_pressed[3] = true },
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => { cpu.keys_pressed[0xC] = true },
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } => { cpu.keys_pressed[4] = true },
-                Event::KeyDown { keycode: Some(Keycode::W), .. } => { cpu.keys_pressed[5] = true },
-                Event::KeyDown { keycode: Some(Keycode::E), .. } => { cpu.keys_pressed[6] = true },
-                Event::KeyDown { keycode: Some(Keycode::R), .. } => { cpu.keys_pressed[0xD] = true },
-                Event::KeyDown { keycode: Some(Keycode::A), .. } => { cpu.keys_pressed[7] = true },
-                Event::KeyDown { keycode: Some(Keycode::S), .. } => { cpu.keys_pressed[8] = true },
-                Event::KeyDown { keycode: Some(Keycode::D), .. } => { cpu.keys_pressed[9] = true },
-                Event::KeyDown { keycode: Some(Keycode::F), .. } => { cpu.keys_pressed[0xE] = true },
-                Event::KeyDown { keycode: Some(Keycode::Z), .. } => { cpu.keys_pressed[0xA] = true },
-                Event::KeyDown { keycode: Some(Keycode::X), .. } => { cpu.keys_pressed[0] = true },
-                Event::KeyDown { keycode: Some(Keycode::C), .. } => { cpu.keys_pressed[0xB] = true },
-                Event::KeyDown { keycode: Some(Keycode::V), .. } => { cpu.keys_pressed[0xF] = true },
-                Event::KeyUp { keycode: Some(Keycode::Num1), .. } => { cpu.keys_pressed[1] = false },
-                Event::KeyUp { keycode: Some(Keycode::Num2), .. } => { cpu.keys_pressed[2] = false },
-                Event::KeyUp { keycode: Some(Keycode::Num3), .. } => { cpu.keys_pressed[3] = false },
-                Event::KeyUp { keycode: Some(Keycode::Num4), .. } => { cpu.keys_pressed[0xC] = false },
-                Event::KeyUp { keycode: Some(Keycode::Q), .. } => { cpu.keys_pressed[4] = false },
-                Event::KeyUp { keycode: Some(Keycode::W), .. } => { cpu.keys_pressed[5] = false },
-                Event::KeyUp { keycode: Some(Keycode::E), .. } => { cpu.keys_pressed[6] = false },
-                Event::KeyUp { keycode: Some(Keycode::R), .. } => { cpu.keys_pressed[0xD] = false },
-                Event::KeyUp { keycode: Some(Keycode::A), .. } => { cpu.keys_pressed[7] = false },
-                Event::KeyUp { keycode: Some(Keycode::S), .. } => { cpu.keys_pressed[8] = false },
-                Event::KeyUp { keycode: Some(Keycode::D), .. } => { cpu.keys_pressed[9] = false },
-                Event::KeyUp { keycode: Some(Keycode::F), .. } => { cpu.keys_pressed[0xE] = false },
-                Event::KeyUp { keycode: Some(Keycode::Z), .. } => { cpu.keys_pressed[0xA] = false },
-                Event::KeyUp { keycode: Some(Keycode::X), .. } => { cpu.keys_pressed[0] = false },
-                Event::KeyUp { keycode: Some(Keycode::C), .. } => { cpu.keys_pressed[0xB] = false },
-                Event::KeyUp { keycode: Some(Keycode::V), .. } => { cpu.keys_pressed[0xF] = false },
+                Event::Quit { .. } => break 'main,
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'main,
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => playback.toggle_pause(),
+                Event::KeyDown { keycode: Some(Keycode::K), .. } => playback.advance_frame(),
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => playback.cycle_slow_motion(),
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => playback.set_fast_forward(true),
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => playback.set_fast_forward(false),
+                //rewinding or loading a state would break a movie
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => rewinding = !movie_active,
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => rewinding = false,
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat, .. } => {
+                    if let Some(slot) = state_slot(keycode) {
+                        //the editor launches the ROM in DAP mode, so there is no path to put slots next to
+                        match filename.as_ref() {
+                            _ if repeat => {}
+                            Some(rom) if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => save_slot(&machine, rom, slot),
+                            Some(_) if movie_active => eprintln!("States cannot be loaded while recording or playing a movie"),
+                            Some(rom) => load_slot(&mut machine, rom, slot),
+                            None => eprintln!("Save states need a ROM path on the command line")
+                        }
+                    } else if let Some(key) = keypad_key(keycode) {
+                        if player.is_none() { machine.set_key(key, true) }
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(key) = keypad_key(keycode) {
+                        if player.is_none() { machine.set_key(key, false) }
+                    }
+                },
                 _ => {}
             }
         }
-        //cpu
-        cpu.cycle(&mut gfx);
+        //timers tick once per emulated frame, so stretching frames slows them and the audio along with everything else
+        if playback.speed() != speed{
+            speed = playback.speed();
+            if let Some(speed) = speed{
+                scheduler.set_speed(speed);
+                speaker.set_speed(speed as f32);
+            }
+        }
+        let mut state = playback.title();
+        if rewinding{
+            state.push_str(" - Rewinding");
+        }
+        if recorder.is_some(){
+            state.push_str(" - Recording");
+        }
+        if player.is_some(){
+            state.push_str(" - Playing movie");
+        }
+        if state != title{
+            title = state;
+            canvas.window_mut().set_title(&title).expect("Could not set the window title");
+        }
+        //while paused only input is polled, unless a single frame was asked for
+        let run_frame = playback.take_frame();
+        if run_frame && rewinding{
+            //the keyboard is not part of the past, so keys held now stay held
+            let keys = machine.cpu().keys_pressed;
+            if let Err(error) = rewind.step_back(&mut machine){
+                eprintln!("Could not rewind: {}", error);
+                rewind.clear();
+            }
+            machine.cpu_mut().keys_pressed = keys;
+        } else if run_frame{
+            //movies log or replace the keypad once per frame
+            if let Some(recorder) = recorder.as_mut(){
+                recorder.record_frame(&machine.cpu().keys_pressed);
+            }
+            if let Some(player) = player.as_mut(){
+                match player.next_frame(){
+                    Some(keys) => machine.cpu_mut().keys_pressed = keys,
+                    None => break
+                }
+            }
+            //cpu, the same instructions Machine::run_frame would run with the debuggers checked before each one
+            machine.begin_frame();
+            while machine.frame_pending(){
+                //debuggers
+                if let Some(debugger) = debugger.as_mut(){
+                    if !debugger_prompt(debugger, &mut machine, &mut canvas, &palette, &speaker){
+                        break 'main;
+                    }
+                }
+                if !check_remote(&mut dap, &mut machine) || !check_remote(&mut gdb, &mut machine){
+                    break 'main;
+                }
+                if let Err(error) = machine.step(){
+                    let message = format!("Emulation stopped: {}", error);
+                    eprintln!("{}", message);
+                    report_remote_error(&mut gdb, &message);
+                    report_remote_error(&mut dap, &message);
+                    break 'main;
+                }
+            }
+            //timers
+            machine.tick_timers();
+            if !movie_active{
+                rewind.record(&machine);
+            }
+        }
+        if machine.exited(){
+            if let Some(stub) = gdb.as_mut(){
+                let _ = stub.report_exit(0);
+            }
+            if let Some(server) = dap.as_mut(){
+                let _ = server.report_exit(0);
+            }
+            break;
+        }
         //render
-        if cpu.draw_flag{
-            gfx.render(&mut canvas);
-            cpu.draw_flag = false;
+        if machine.take_draw_flag(){
+            machine.framebuffer().render(&mut canvas, &palette);
         }
         //audio
-        if cpu.sound_flag{
+        if machine.take_audio_flag(){
+            speaker.set_pattern(machine.cpu().audio_pattern());
+            speaker.set_pitch(machine.cpu().pitch());
+        }
+        //uncapped fast forward has no speed to play audio at
+        if machine.sound_active() && !rewinding && !playback.is_paused() && speed.is_some(){
             speaker.start();
         } else {
             speaker.stop();
         }
-        //timer
-        if last_tick_time.elapsed().expect("Clock error") >= Duration::new(0, 1_000_000_000u32 / 60){
-            cpu.timer();
-            last_tick_time = SystemTime::now()
+        if speed.is_some(){
+            scheduler.wait();
+        }
+    }
+
+    if let (Some(recorder), Some(path)) = (recorder, record_path){
+        match write(&path, recorder.finish(&machine).to_bytes()){
+            Ok(()) => eprintln!("Recorded movie to {}", path),
+            Err(error) => eprintln!("Could not save {}: {}", path, error)
+        }
+    }
+    if let Some(player) = player{
+        if let Err(error) = player.verify(&machine){
+            eprintln!("{}", error);
+            exit(1);
         }
-        //sleep(Duration::from_millis(1));
-        sleep(Duration::new(0, 1_000_000_000u32 / 2500))
-     }
+        eprintln!("Playback finished on the recorded state");
+    }
+}
 
+//maps the left side of a QWERTY keyboard onto the hex keypad
+//1 2 3 4      1 2 3 C
+//Q W E R  ->  4 5 6 D
+//A S D F      7 8 9 E
+//Z X C V      A 0 B F
+fn keypad_key(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(0xC),
+        Keycode::Q => Some(4),
+        Keycode::W => Some(5),
+        Keycode::E => Some(6),
+        Keycode::R => Some(0xD),
+        Keycode::A => Some(7),
+        Keycode::S => Some(8),
+        Keycode::D => Some(9),
+        Keycode::F => Some(0xE),
+        Keycode::Z => Some(0xA),
+        Keycode::X => Some(0),
+        Keycode::C => Some(0xB),
+        Keycode::V => Some(0xF),
+        _ => None
+    }
+}
+
+//runs the command line debugger while it has the machine stopped, false once it asks to quit
+fn debugger_prompt(debugger: &mut Debugger, machine: &mut Machine, canvas: &mut WindowCanvas, palette: &Palette, speaker: &Speaker) -> bool{
+    while debugger.check(machine){
+        for message in debugger.take_log(){
+            println!("{}", message);
+        }
+        if machine.take_draw_flag(){
+            machine.framebuffer().render(canvas, palette);
+        }
+        speaker.stop();
+        println!("{}", debugger.location(machine));
+        print!("(ferric-8) ");
+        stdout().flush().expect("Could not write to stdout");
+        let mut line = String::new();
+        if stdin().read_line(&mut line).expect("Could not read from stdin") == 0{
+            return false;
+        }
+        let output = debugger.execute(machine, &line);
+        for message in debugger.take_log(){
+            println!("{}", message);
+        }
+        if !output.is_empty(){
+            println!("{}", output);
+        }
+        if debugger.quit_requested() || machine.exited(){
+            return false;
+        }
+    }
+    for message in debugger.take_log(){
+        println!("{}", message);
+    }
+    true
+}
+
+//serves gdb or the debug adapter before an instruction, dropping the connection if it is lost
+//false once the client wants emulation to stop
+fn check_remote<D: RemoteDebugger>(remote: &mut Option<D>, machine: &mut Machine) -> bool{
+    if let Some(client) = remote.as_mut(){
+        if let Err(error) = client.check(machine){
+            eprintln!("Lost connection to {}: {}", client.client_name(), error);
+            *remote = None;
+        } else if client.quit_requested(){
+            return false;
+        }
+    }
+    true
+}
+
+fn report_remote_error<D: RemoteDebugger>(remote: &mut Option<D>, message: &str){
+    if let Some(client) = remote.as_mut(){
+        let _ = client.report_error(message);
+    }
+}
+
+//F1 to F9 pick a save state slot
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        _ => None
+    }
+}
+
+//slots live next to the ROM, e.g. pong.ch8 -> pong.state1
+fn slot_path(rom: &str, slot: u8) -> PathBuf {
+    Path::new(rom).with_extension(format!("state{}", slot))
+}
+
+fn save_slot(machine: &Machine, rom: &str, slot: u8) {
+    let path = slot_path(rom, slot);
+    match write(&path, machine.save_state()) {
+        Ok(()) => eprintln!("Saved state to {}", path.display()),
+        Err(error) => eprintln!("Could not save {}: {}", path.display(), error)
+    }
+}
+
+fn load_slot(machine: &mut Machine, rom: &str, slot: u8) {
+    let path = slot_path(rom, slot);
+    let state = match read(&path) {
+        Ok(state) => state,
+        Err(error) => {
+            eprintln!("Could not load {}: {}", path.display(), error);
+            return;
+        }
+    };
+    match machine.load_state(&state) {
+        Ok(()) => eprintln!("Loaded state from {}", path.display()),
+        Err(error) => eprintln!("Could not load {}: {}", path.display(), error)
+    }
 }
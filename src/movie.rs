@@ -0,0 +1,306 @@
+use crate::error::EmulatorError;
+use crate::machine::Machine;
+use crate::quirks::Quirks;
+use crate::save_state::{StateReader, StateWriter};
+
+pub const MAGIC: &[u8; 4] = b"F8MV";
+pub const VERSION: u16 = 4;
+
+//64 bit FNV-1a, used to identify ROMs and compare final machine states
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+    hash
+}
+
+//the keypad as a bitmask, key n is bit n
+fn pack_keys(keys: &[bool; 16]) -> u16 {
+    keys.iter().enumerate().fold(0, |mask, (key, &pressed)| mask | (pressed as u16) << key)
+}
+
+fn unpack_keys(mask: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = mask & (1 << key) != 0;
+    }
+    keys
+}
+
+//everything needed to replay a run frame by frame: how the machine was set up,
+//the keypad whenever it changed, and a hash of the state it ended in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub vip_timing: bool,
+    pub frames: u32,
+    //(frame, keypad) pairs in frame order, the keypad holds until the next change
+    pub inputs: Vec<(u32, u16)>,
+    pub final_hash: u64
+}
+
+impl Movie {
+    pub fn parse(bytes: &[u8]) -> Result<Movie, EmulatorError> {
+        Movie::read(bytes).map_err(|error| match error {
+            EmulatorError::InvalidSaveState(reason) => EmulatorError::InvalidMovie(reason),
+            error => error
+        })
+    }
+
+    fn read(bytes: &[u8]) -> Result<Movie, EmulatorError> {
+        let mut reader = StateReader::with_header(bytes, MAGIC, VERSION)?;
+        let rom_hash = reader.read_u64()?;
+        let seed = reader.read_u64()?;
+        //version 3 added the display wait quirk and version 4 the extended memory quirk
+        let has_display_wait = reader.version() >= 3;
+        let has_extended_memory = reader.version() >= 4;
+        let quirks = Quirks::load_state(&mut reader, has_display_wait, has_extended_memory)?;
+        let instructions_per_frame = reader.read_u32()?;
+        //version 1 movies predate VIP timing
+        let vip_timing = reader.version() >= 2 && reader.read_bool()?;
+        let frames = reader.read_u32()?;
+        let final_hash = reader.read_u64()?;
+        let mut inputs = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let frame = reader.read_u32()?;
+            if frame >= frames || inputs.last().is_some_and(|&(last, _)| frame <= last) {
+                return Err(EmulatorError::InvalidMovie(format!("input at frame {} is out of order", frame)));
+            }
+            inputs.push((frame, reader.read_u16()?));
+        }
+        reader.finish()?;
+        Ok(Movie { rom_hash, seed, quirks, instructions_per_frame, vip_timing, frames, inputs, final_hash })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_header(MAGIC, VERSION);
+        writer.write_u64(self.rom_hash);
+        writer.write_u64(self.seed);
+        self.quirks.save_state(&mut writer);
+        writer.write_u32(self.instructions_per_frame);
+        writer.write_bool(self.vip_timing);
+        writer.write_u32(self.frames);
+        writer.write_u64(self.final_hash);
+        writer.write_u32(self.inputs.len() as u32);
+        for &(frame, keys) in self.inputs.iter() {
+            writer.write_u32(frame);
+            writer.write_u16(keys);
+        }
+        writer.finish()
+    }
+
+    //a machine set up exactly as the recording one was, refusing a different ROM
+    pub fn machine(&self, rom: &[u8]) -> Result<Machine, EmulatorError> {
+        if fnv1a(rom) != self.rom_hash {
+            return Err(EmulatorError::InvalidMovie(String::from("recorded with a different ROM")));
+        }
+        let mut machine = Machine::new(self.quirks);
+        machine.load_rom(rom)?;
+        machine.seed(self.seed);
+        machine.set_instructions_per_frame(self.instructions_per_frame as usize);
+        machine.set_vip_timing(self.vip_timing);
+        Ok(machine)
+    }
+
+    //plays the whole movie without a frontend, for regression tests
+    pub fn replay(&self, rom: &[u8]) -> Result<Machine, EmulatorError> {
+        let mut machine = self.machine(rom)?;
+        let mut player = Player::new(self.clone());
+        while let Some(keys) = player.next_frame() {
+            if machine.exited() {
+                break;
+            }
+            machine.cpu_mut().keys_pressed = keys;
+            machine.run_frame()?;
+        }
+        player.verify(&machine)?;
+        Ok(machine)
+    }
+}
+
+pub struct Recorder {
+    movie: Movie,
+    keys: Option<u16>
+}
+
+impl Recorder {
+    //seeds the machine so the run can be reproduced, call before the first frame
+    pub fn start(rom: &[u8], seed: u64, machine: &mut Machine) -> Recorder {
+        machine.seed(seed);
+        Recorder {
+            movie: Movie {
+                rom_hash: fnv1a(rom),
+                seed,
+                quirks: machine.cpu().quirks(),
+                instructions_per_frame: machine.instructions_per_frame() as u32,
+                vip_timing: machine.vip_timing(),
+                frames: 0,
+                inputs: Vec::new(),
+                final_hash: 0
+            },
+            keys: None
+        }
+    }
+
+    //call with the keypad just before each frame runs
+    pub fn record_frame(&mut self, keys: &[bool; 16]) {
+        let mask = pack_keys(keys);
+        if self.keys != Some(mask) {
+            self.movie.inputs.push((self.movie.frames, mask));
+            self.keys = Some(mask);
+        }
+        self.movie.frames += 1;
+    }
+
+    pub fn finish(mut self, machine: &Machine) -> Movie {
+        self.movie.final_hash = fnv1a(&machine.save_state());
+        self.movie
+    }
+}
+
+pub struct Player {
+    movie: Movie,
+    frame: u32,
+    next_input: usize,
+    keys: [bool; 16]
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Player {
+        Player { movie, frame: 0, next_input: 0, keys: [false; 16] }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    //the keypad for the next frame, or None once every frame has been played
+    pub fn next_frame(&mut self) -> Option<[bool; 16]> {
+        if self.frame >= self.movie.frames {
+            return None;
+        }
+        if let Some(&(frame, mask)) = self.movie.inputs.get(self.next_input) {
+            if frame == self.frame {
+                self.keys = unpack_keys(mask);
+                self.next_input += 1;
+            }
+        }
+        self.frame += 1;
+        Some(self.keys)
+    }
+
+    //checks the machine ended where the recording did
+    pub fn verify(&self, machine: &Machine) -> Result<(), EmulatorError> {
+        if self.frame < self.movie.frames {
+            return Err(EmulatorError::InvalidMovie(format!("playback stopped at frame {} of {}", self.frame, self.movie.frames)));
+        }
+        let actual = fnv1a(&machine.save_state());
+        if actual != self.movie.final_hash {
+            return Err(EmulatorError::MovieDesync { expected: self.movie.final_hash, actual });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    //draws random sprites and counts frames with key 5 down in V5
+    fn rom() -> Vec<u8> {
+        assemble("loop: RND V0, 0x3F\nRND V1, 0x1F\nLD I, 0x50\nDRW V0, V1, 5\nLD V2, 5\nSKNP V2\nADD V5, 1\nJP loop").unwrap()
+    }
+
+    fn record(rom: &[u8]) -> (Movie, Machine) {
+        let mut machine = Machine::new(Quirks::vip());
+        machine.load_rom(rom).unwrap();
+        machine.set_instructions_per_frame(8);
+        let mut recorder = Recorder::start(rom, 1234, &mut machine);
+        for frame in 0..30 {
+            machine.set_key(5, (10..20).contains(&frame));
+            recorder.record_frame(&machine.cpu().keys_pressed);
+            machine.run_frame().unwrap();
+        }
+        (recorder.finish(&machine), machine)
+    }
+
+    #[test]
+    fn fnv1a_test() {
+        assert_eq!(0xCBF2_9CE4_8422_2325, fnv1a(b""));
+        assert_eq!(0xAF63_DC4C_8601_EC8C, fnv1a(b"a"));
+        assert_eq!(0x8594_4171_F739_67E8, fnv1a(b"foobar"));
+    }
+
+    #[test]
+    fn record_test() {
+        let (movie, machine) = record(&rom());
+        assert_eq!(30, movie.frames);
+        assert_eq!(vec![(0, 0), (10, 1 << 5), (20, 0)], movie.inputs);
+        assert_eq!(8, movie.instructions_per_frame);
+        assert_eq!(Quirks::vip(), movie.quirks);
+        assert!(machine.cpu().v()[5].read_reg() > 0);
+        assert_eq!(movie, Movie::parse(&movie.to_bytes()).unwrap());
+    }
+
+    #[test]
+    fn vip_timing_test() {
+        let rom = rom();
+        let mut machine = Machine::default();
+        machine.load_rom(&rom).unwrap();
+        machine.set_vip_timing(true);
+        let mut recorder = Recorder::start(&rom, 5, &mut machine);
+        for _ in 0..10 {
+            recorder.record_frame(&machine.cpu().keys_pressed);
+            machine.run_frame().unwrap();
+        }
+        let movie = recorder.finish(&machine);
+        assert!(movie.vip_timing);
+        assert_eq!(machine.save_state(), movie.replay(&rom).unwrap().save_state());
+
+        //a version 1 movie has no timing flag or display wait and extended memory quirks, and replays with
+        //instruction counts
+        let mut bytes = movie.to_bytes();
+        bytes[5] = 1;
+        bytes.remove(4 + 2 + 8 + 8 + 7 + 4);
+        bytes.drain(4 + 2 + 8 + 8 + 5..4 + 2 + 8 + 8 + 7);
+        let old = Movie::parse(&bytes).unwrap();
+        assert!(!old.vip_timing);
+        assert_eq!(movie.inputs, old.inputs);
+    }
+
+    #[test]
+    fn replay_test() {
+        let rom = rom();
+        let (movie, recorded) = record(&rom);
+        let replayed = movie.replay(&rom).unwrap();
+        assert_eq!(recorded.save_state(), replayed.save_state());
+
+        let mut changed = movie.clone();
+        changed.inputs[1].0 = 11;
+        assert!(matches!(changed.replay(&rom), Err(EmulatorError::MovieDesync{..})));
+        changed.seed += 1;
+        changed.inputs[1].0 = 10;
+        assert!(matches!(changed.replay(&rom), Err(EmulatorError::MovieDesync{..})));
+        let mut player = Player::new(movie.clone());
+        player.next_frame();
+        assert!(matches!(player.verify(&recorded), Err(EmulatorError::InvalidMovie(_))));
+        assert!(matches!(movie.replay(&assemble("JP 0x200").unwrap()), Err(EmulatorError::InvalidMovie(_))));
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        let (movie, _) = record(&rom());
+        let bytes = movie.to_bytes();
+        assert!(matches!(Movie::parse(&bytes[..bytes.len() - 1]), Err(EmulatorError::InvalidMovie(_))));
+        assert!(matches!(Movie::parse(&Machine::default().save_state()), Err(EmulatorError::InvalidMovie(_))));
+        let mut unordered = movie.clone();
+        unordered.inputs.swap(0, 1);
+        assert!(Movie::parse(&unordered.to_bytes()).is_err());
+    }
+}
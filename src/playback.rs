@@ -0,0 +1,130 @@
+//slow motion speeds, cycled through by a hotkey
+pub const SLOW_MOTION: [f64; 3] = [1.0, 0.5, 0.25];
+
+//how the frontend runs frames: paused, held fast forward, slow motion and single frame advance
+pub struct Playback {
+    paused: bool,
+    fast_forward: bool,
+    //None runs as fast as the host allows
+    fast_forward_speed: Option<f64>,
+    slow_motion: usize,
+    advance: bool
+}
+
+impl Playback {
+    pub fn new(fast_forward_speed: Option<f64>) -> Playback {
+        Playback {
+            paused: false,
+            fast_forward: false,
+            fast_forward_speed,
+            slow_motion: 0,
+            advance: false
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = false;
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn cycle_slow_motion(&mut self) {
+        self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION.len();
+    }
+
+    //runs a single frame, only while paused
+    pub fn advance_frame(&mut self) {
+        self.advance = self.paused;
+    }
+
+    //whether the next frame should run, using up a pending frame advance
+    pub fn take_frame(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+        let advance = self.advance;
+        self.advance = false;
+        advance
+    }
+
+    //emulated seconds per real second, None when fast forward is uncapped
+    pub fn speed(&self) -> Option<f64> {
+        if self.fast_forward {
+            self.fast_forward_speed
+        } else {
+            Some(SLOW_MOTION[self.slow_motion])
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let mut title = String::from("ferric-8");
+        if self.paused {
+            title.push_str(" - Paused");
+        } else if self.fast_forward {
+            match self.fast_forward_speed {
+                Some(speed) => title.push_str(&format!(" - Fast forward {}x", speed)),
+                None => title.push_str(" - Fast forward")
+            }
+        } else if self.slow_motion > 0 {
+            title.push_str(&format!(" - Slow motion {}x", SLOW_MOTION[self.slow_motion]));
+        }
+        title
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Playback {
+        Playback::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pause_test() {
+        let mut playback = Playback::default();
+        assert!(playback.take_frame());
+        playback.advance_frame();
+        assert!(playback.take_frame());
+        playback.toggle_pause();
+        assert_eq!("ferric-8 - Paused", playback.title());
+        assert!(!playback.take_frame());
+        playback.advance_frame();
+        assert!(playback.take_frame());
+        assert!(!playback.take_frame());
+        playback.toggle_pause();
+        assert!(playback.take_frame());
+        assert_eq!("ferric-8", playback.title());
+    }
+
+    #[test]
+    fn speed_test() {
+        let mut playback = Playback::new(Some(4.0));
+        assert_eq!(Some(1.0), playback.speed());
+        playback.cycle_slow_motion();
+        assert_eq!(Some(0.5), playback.speed());
+        assert_eq!("ferric-8 - Slow motion 0.5x", playback.title());
+        playback.cycle_slow_motion();
+        assert_eq!(Some(0.25), playback.speed());
+        playback.set_fast_forward(true);
+        assert_eq!(Some(4.0), playback.speed());
+        assert_eq!("ferric-8 - Fast forward 4x", playback.title());
+        playback.set_fast_forward(false);
+        playback.cycle_slow_motion();
+        assert_eq!(Some(1.0), playback.speed());
+
+        let mut uncapped = Playback::default();
+        uncapped.set_fast_forward(true);
+        assert_eq!(None, uncapped.speed());
+        assert_eq!("ferric-8 - Fast forward", uncapped.title());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::error::EmulatorError;
+use crate::save_state::{StateReader, StateWriter};
+
+//behaviours that differ between CHIP-8 interpreters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    //8XY6/8XYE shift VX in place instead of loading VY first
+    pub shift: bool,
+    //FX55/FX65 leave I pointing past the last register transferred
+    pub load_store: bool,
+    //BNNN behaves as BXNN and adds VX instead of V0
+    pub jump: bool,
+    //8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    //DXYN clips sprites at the screen edge instead of wrapping them
+    pub clipping: bool,
+    //DXYN waits for the next vertical blank, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+    //the whole 64 KiB of XO-CHIP memory can be addressed instead of the 4 KiB of the original machines
+    pub extended_memory: bool
+}
+
+impl Quirks {
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift: false,
+            load_store: true,
+            jump: false,
+            vf_reset: true,
+            clipping: true,
+            display_wait: true,
+            extended_memory: false
+        }
+    }
+
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+            extended_memory: false
+        }
+    }
+
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: false,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+            extended_memory: false
+        }
+    }
+
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift: false,
+            load_store: true,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+            extended_memory: true
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Quirks::vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::schip()),
+            "xochip" | "xo-chip" => Some(Quirks::xo_chip()),
+            _ => None
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for quirk in [self.shift, self.load_store, self.jump, self.vf_reset, self.clipping, self.display_wait,
+            self.extended_memory] {
+            writer.write_bool(quirk);
+        }
+    }
+
+    //files written before the display wait or extended memory quirks existed leave them off
+    pub fn load_state(reader: &mut StateReader, has_display_wait: bool, has_extended_memory: bool)
+        -> Result<Quirks, EmulatorError> {
+        Ok(Quirks {
+            shift: reader.read_bool()?,
+            load_store: reader.read_bool()?,
+            jump: reader.read_bool()?,
+            vf_reset: reader.read_bool()?,
+            clipping: reader.read_bool()?,
+            display_wait: has_display_wait && reader.read_bool()?,
+            extended_memory: has_extended_memory && reader.read_bool()?
+        })
+    }
+}
+
+//matches the behaviour ferric-8 has always had
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: false,
+            jump: false,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+            extended_memory: false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_test() {
+        assert_eq!(Some(Quirks::vip()), Quirks::from_name("VIP"));
+        assert_eq!(Some(Quirks::chip48()), Quirks::from_name("chip-48"));
+        assert_eq!(Some(Quirks::schip()), Quirks::from_name("schip"));
+        assert_eq!(Some(Quirks::xo_chip()), Quirks::from_name("xochip"));
+        assert_eq!(None, Quirks::from_name("nes"));
+    }
+}
@@ -1,12 +1,58 @@
+use crate::error::EmulatorError;
+use crate::save_state::{StateReader, StateWriter};
+use std::cell::RefCell;
 use std::fs::read;
 
+//XO-CHIP extends the address space to the full 16 bits
+pub const MEMORY_SIZE: usize = 0x10000;
+//all that CHIP-8 and SUPER-CHIP programs can address
+pub const ORIGINAL_MEMORY_SIZE: usize = 0x1000;
+const PROGRAM_START: usize = 0x200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write
+}
+
+//an inclusive address range that records every read and/or write made by the CPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool
+}
+
+impl Watchpoint {
+    fn matches(&self, address: u16, access: Access) -> bool {
+        let wanted = match access {
+            Access::Read => self.read,
+            Access::Write => self.write
+        };
+        wanted && address >= self.start && address <= self.end
+    }
+}
+
+//a watched access, reads have the same old and new value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub access: Access,
+    pub old: u8,
+    pub new: u8
+}
+
 pub struct RAM {
-    memory: [u8; 4096]
+    memory: Vec<u8>,
+    watchpoints: Vec<Watchpoint>,
+    //reads only borrow memory, so hits are collected behind a RefCell
+    watch_hits: RefCell<Vec<WatchHit>>
 }
 
 impl RAM {
     pub fn new() -> RAM {
-        let mut ram = RAM {memory: [0; 4096]};
+        let mut ram = RAM {memory: vec![0; MEMORY_SIZE], watchpoints: Vec::new(), watch_hits: RefCell::new(Vec::new())};
         let font :[u8; 80] =
             [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -28,20 +74,96 @@ impl RAM {
         for i in 0..80 {
             ram.write_byte(i + 0x50, font[i as usize])
         }
+        //SUPER-CHIP 8x10 font, placed straight after the small one
+        let big_font :[u8; 160] =
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0]; // F
+
+        for i in 0..160 {
+            ram.write_byte(i + 0xA0, big_font[i as usize])
+        }
         ram
     }
 
     pub fn read_byte(&self, adr: u16) -> u8 {
-        self.memory[adr as usize]
+        let val = self.memory[adr as usize];
+        self.watch(adr, Access::Read, val, val);
+        val
     }
 
     pub fn write_byte(&mut self, adr: u16, val: u8) {
+        self.watch(adr, Access::Write, self.memory[adr as usize], val);
         self.memory[adr as usize] = val
     }
 
+    //reads a byte without triggering watchpoints, for debuggers and frontends
+    pub fn peek(&self, adr: u16) -> u8 {
+        self.memory[adr as usize]
+    }
+
+    //writes a byte without triggering watchpoints, for debuggers
+    pub fn poke(&mut self, adr: u16, val: u8) {
+        self.memory[adr as usize] = val
+    }
+
+    fn watch(&self, address: u16, access: Access, old: u8, new: u8) {
+        if self.watchpoints.iter().any(|watchpoint| watchpoint.matches(address, access)) {
+            self.watch_hits.borrow_mut().push(WatchHit{address, access, old, new});
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    //removes every watchpoint starting at the address, returning whether there was one
+    pub fn remove_watchpoint(&mut self, start: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.start != start);
+        self.watchpoints.len() != count
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    //watched accesses since the last call, oldest first
+    pub fn take_watch_hits(&self) -> Vec<WatchHit> {
+        self.watch_hits.borrow_mut().drain(..).collect()
+    }
+
+    //watchpoints belong to the debugger, so only memory is saved
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.memory);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<RAM, EmulatorError> {
+        let mut ram = RAM::new();
+        ram.memory.copy_from_slice(reader.read_bytes(MEMORY_SIZE)?);
+        Ok(ram)
+    }
+
     pub fn read_word(&self, adr: u16) -> u16 {
         let first_byte = self.memory[adr as usize] as u16;
-        let second_byte =  self.memory[(adr + 1) as usize] as u16;
+        let second_byte =  self.memory[adr.wrapping_add(1) as usize] as u16;
         (first_byte << 8) + second_byte
     }
 
@@ -49,7 +171,7 @@ impl RAM {
         let first_byte = (val >> 8) as u8;
         let second_byte =  (val & (0x00FF)) as u8;
         self.memory[adr as usize] = first_byte;
-        self.memory[(adr + 1) as usize] = second_byte;
+        self.memory[adr.wrapping_add(1) as usize] = second_byte;
     }
 
     pub fn core_dump(&self) {
@@ -58,26 +180,38 @@ impl RAM {
             print!(" {:02X}", i);
         }
         println!();
-        for i in 0..256{
+        for i in 0..(MEMORY_SIZE / 16) as u16{
             print!("0x{:04X}", i*16);
             for j in 0..16{
-                print!(" {:02X}", self.read_byte(i*16 + j))
+                print!(" {:02X}", self.peek(i*16 + j))
             }
             println!();
         }
 
     }
-    pub fn load_rom(&mut self, filename: String){
-        let rom_bytes = read(filename).unwrap();
-        let mut address: u16 = 0x200;
-        for i in &rom_bytes{
-            self.write_byte(address, *i);
-            address += 1;
+    pub fn load_rom(&mut self, filename: String) -> Result<(), EmulatorError>{
+        let rom_bytes = read(filename)?;
+        self.load_bytes(&rom_bytes)
+    }
+
+    //copies a program into memory at the usual load address
+    pub fn load_bytes(&mut self, rom_bytes: &[u8]) -> Result<(), EmulatorError>{
+        let max = MEMORY_SIZE - PROGRAM_START;
+        if rom_bytes.len() > max{
+            return Err(EmulatorError::RomTooLarge{size: rom_bytes.len(), max});
         }
+        self.memory[PROGRAM_START..PROGRAM_START + rom_bytes.len()].copy_from_slice(rom_bytes);
+        Ok(())
     }
 }
 
 
+impl Default for RAM {
+    fn default() -> RAM {
+        RAM::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,14 +246,75 @@ mod tests {
         assert_eq!(0x812C, ram.read_word(0x3A2));
     }
 
+    #[test]
+    fn read_write_extended_test() {
+        let mut ram = RAM::new();
+        ram.write_word(0xFFF0, 0x1234);
+        assert_eq!(0x1234, ram.read_word(0xFFF0));
+        ram.write_word(0xFFFF, 0xABCD);
+        assert_eq!(0xAB, ram.read_byte(0xFFFF));
+        assert_eq!(0xCD, ram.read_byte(0x0000));
+    }
+
     #[test]
     fn load_rom_test(){
         let mut ram = RAM::new();
-        ram.load_rom(String::from("test/readtest.ch8"));
+        ram.load_rom(String::from("test/readtest.ch8")).unwrap();
         assert_eq!(0, ram.read_byte(0x1FF));
         assert_eq!(0, ram.read_byte(0x200));
         assert_eq!(0x11, ram.read_byte(0x201));
         assert_eq!(0xFF, ram.read_byte(0x20F));
         assert_eq!(0, ram.read_byte(0x210));
     }
+
+    #[test]
+    fn load_bytes_test(){
+        let mut ram = RAM::new();
+        ram.load_bytes(&[0x12, 0x34, 0x56]).unwrap();
+        assert_eq!(0x1234, ram.read_word(0x200));
+        assert_eq!(0x56, ram.read_byte(0x202));
+    }
+
+    #[test]
+    fn load_rom_missing_test(){
+        let mut ram = RAM::new();
+        match ram.load_rom(String::from("test/missing.ch8")) {
+            Err(EmulatorError::Io(_)) => {}
+            other => panic!("expected an I/O error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn load_bytes_too_large_test(){
+        let mut ram = RAM::new();
+        assert!(ram.load_bytes(&vec![0xAA; MEMORY_SIZE - 0x200]).is_ok());
+        match ram.load_bytes(&vec![0xAA; MEMORY_SIZE - 0x1FF]) {
+            Err(EmulatorError::RomTooLarge{size, max}) => {
+                assert_eq!(MEMORY_SIZE - 0x1FF, size);
+                assert_eq!(MEMORY_SIZE - 0x200, max);
+            }
+            other => panic!("expected the ROM to be too large, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn watchpoint_test(){
+        let mut ram = RAM::new();
+        ram.write_byte(0x300, 0x11);
+        ram.add_watchpoint(Watchpoint{start: 0x300, end: 0x301, read: false, write: true});
+        ram.write_byte(0x300, 0x22);
+        ram.write_byte(0x302, 0x33);
+        ram.poke(0x301, 0x44);
+        assert_eq!(0x44, ram.peek(0x301));
+        assert_eq!(0x22, ram.read_byte(0x300));
+        assert_eq!(vec![WatchHit{address: 0x300, access: Access::Write, old: 0x11, new: 0x22}], ram.take_watch_hits());
+        assert!(ram.take_watch_hits().is_empty());
+        ram.add_watchpoint(Watchpoint{start: 0x302, end: 0x302, read: true, write: false});
+        ram.read_byte(0x302);
+        ram.peek(0x302);
+        assert_eq!(vec![WatchHit{address: 0x302, access: Access::Read, old: 0x33, new: 0x33}], ram.take_watch_hits());
+        assert!(ram.remove_watchpoint(0x300));
+        assert!(!ram.remove_watchpoint(0x300));
+        assert_eq!(1, ram.watchpoints().len());
+    }
 }
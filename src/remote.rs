@@ -0,0 +1,14 @@
+use crate::machine::Machine;
+use std::io;
+
+//a debugger on the other end of a connection, the gdb stub or the debug adapter
+pub trait RemoteDebugger {
+    //called before every instruction, blocks while the client has the ROM stopped
+    fn check(&mut self, machine: &mut Machine) -> io::Result<()>;
+    //true once the client wants emulation to stop
+    fn quit_requested(&self) -> bool;
+    //tells the client the ROM stopped with an error
+    fn report_error(&mut self, message: &str) -> io::Result<()>;
+    //who is connected, for reporting a lost connection
+    fn client_name(&self) -> &'static str;
+}
@@ -0,0 +1,232 @@
+use crate::error::EmulatorError;
+use crate::machine::Machine;
+use std::collections::VecDeque;
+
+pub const DEFAULT_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+pub const DEFAULT_INTERVAL: usize = 1;
+
+//a bounded history of save states for running the game backwards
+//only the newest snapshot is kept whole, every older one is stored as the
+//compressed XOR against the snapshot after it, which is almost entirely zeros
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    //oldest first, deltas[n] turns snapshot n + 1 back into snapshot n
+    deltas: VecDeque<Vec<u8>>,
+    memory_limit: usize,
+    memory_used: usize,
+    interval: usize,
+    frames: usize
+}
+
+impl Rewind {
+    //snapshots every interval frames, dropping the oldest once memory_limit bytes are in use
+    pub fn new(memory_limit: usize, interval: usize) -> Rewind {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            memory_limit,
+            memory_used: 0,
+            interval: interval.max(1),
+            frames: 0
+        }
+    }
+
+    //call once per frame
+    pub fn record(&mut self, machine: &Machine) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+        let state = machine.save_state();
+        match self.latest.take() {
+            Some(latest) if latest.len() == state.len() => {
+                let delta = compress(&xor(&latest, &state));
+                self.memory_used += delta.len();
+                self.memory_used -= latest.len();
+                self.deltas.push_back(delta);
+            }
+            _ => self.clear()
+        }
+        self.memory_used += state.len();
+        self.latest = Some(state);
+        while self.memory_used > self.memory_limit {
+            match self.deltas.pop_front() {
+                Some(delta) => self.memory_used -= delta.len(),
+                None => break
+            }
+        }
+    }
+
+    //restores the newest snapshot and forgets it, staying on the oldest once the history runs out
+    //returns false if there is nothing to rewind to
+    pub fn step_back(&mut self, machine: &mut Machine) -> Result<bool, EmulatorError> {
+        let latest = match self.latest.take() {
+            Some(latest) => latest,
+            None => return Ok(false)
+        };
+        machine.load_state(&latest)?;
+        self.frames = 0;
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.memory_used -= delta.len() + latest.len();
+                let previous = xor(&latest, &decompress(&delta, latest.len())?);
+                self.memory_used += previous.len();
+                self.latest = Some(previous);
+            }
+            None => self.latest = Some(latest)
+        }
+        Ok(true)
+    }
+
+    //number of snapshots that can be restored
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.memory_used = 0;
+        self.frames = 0;
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Rewind {
+        Rewind::new(DEFAULT_MEMORY_LIMIT, DEFAULT_INTERVAL)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+//run length encodes zeros as a sequence of (zero count, literal count, literals) with big endian u16 counts
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let zeros = data[position..].iter().take(u16::MAX as usize).take_while(|&&byte| byte == 0).count();
+        position += zeros;
+        let literals = data[position..].iter().take(u16::MAX as usize).take_while(|&&byte| byte != 0).count();
+        compressed.extend_from_slice(&(zeros as u16).to_be_bytes());
+        compressed.extend_from_slice(&(literals as u16).to_be_bytes());
+        compressed.extend_from_slice(&data[position..position + literals]);
+        position += literals;
+    }
+    compressed
+}
+
+pub fn decompress(compressed: &[u8], length: usize) -> Result<Vec<u8>, EmulatorError> {
+    let corrupt = || EmulatorError::InvalidSaveState(String::from("corrupt rewind snapshot"));
+    let mut data = Vec::with_capacity(length);
+    let mut position = 0;
+    while position < compressed.len() {
+        let header = compressed.get(position..position + 4).ok_or_else(corrupt)?;
+        let zeros = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let literals = u16::from_be_bytes([header[2], header[3]]) as usize;
+        position += 4;
+        data.resize(data.len() + zeros, 0);
+        data.extend_from_slice(compressed.get(position..position + literals).ok_or_else(corrupt)?);
+        position += literals;
+    }
+    if data.len() != length {
+        return Err(corrupt());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn compress_test() {
+        let mut data = vec![0; 70000];
+        data[3] = 7;
+        data[4] = 8;
+        data[69999] = 1;
+        let compressed = compress(&data);
+        assert!(compressed.len() < 30);
+        assert_eq!(data, decompress(&compressed, data.len()).unwrap());
+        assert_eq!(vec![1, 2, 0, 3], decompress(&compress(&[1, 2, 0, 3]), 4).unwrap());
+        assert!(compress(&[]).is_empty());
+        assert!(decompress(&compressed, 10).is_err());
+        assert!(decompress(&compressed[..5], data.len()).is_err());
+    }
+
+    fn counter() -> Machine {
+        let mut machine = Machine::default();
+        machine.load_rom(&assemble("loop: ADD V0, 1\nJP loop").unwrap()).unwrap();
+        machine.set_instructions_per_frame(2);
+        machine
+    }
+
+    #[test]
+    fn rewind_test() {
+        let mut machine = counter();
+        let mut rewind = Rewind::default();
+        assert!(!rewind.step_back(&mut machine).unwrap());
+        for _ in 0..5 {
+            machine.run_frame().unwrap();
+            rewind.record(&machine);
+        }
+        assert_eq!(5, rewind.len());
+        for expected in [5, 4, 3, 2, 1, 1] {
+            assert!(rewind.step_back(&mut machine).unwrap());
+            assert_eq!(expected, machine.cpu().v()[0].read_reg());
+        }
+        //recording carries on from the restored frame
+        machine.run_frame().unwrap();
+        rewind.record(&machine);
+        rewind.step_back(&mut machine).unwrap();
+        assert_eq!(2, machine.cpu().v()[0].read_reg());
+    }
+
+    #[test]
+    fn interval_test() {
+        let mut machine = counter();
+        let mut rewind = Rewind::new(DEFAULT_MEMORY_LIMIT, 3);
+        for _ in 0..7 {
+            machine.run_frame().unwrap();
+            rewind.record(&machine);
+        }
+        assert_eq!(2, rewind.len());
+        rewind.step_back(&mut machine).unwrap();
+        assert_eq!(6, machine.cpu().v()[0].read_reg());
+        rewind.step_back(&mut machine).unwrap();
+        assert_eq!(3, machine.cpu().v()[0].read_reg());
+    }
+
+    #[test]
+    fn memory_limit_test() {
+        let mut machine = counter();
+        let snapshot = machine.save_state().len();
+        let mut rewind = Rewind::new(snapshot + 100, 1);
+        for _ in 0..50 {
+            machine.run_frame().unwrap();
+            rewind.record(&machine);
+        }
+        assert!(rewind.memory_used() <= snapshot + 100);
+        assert!(rewind.len() > 1 && rewind.len() < 50);
+        let oldest = 51 - rewind.len() as u8;
+        while rewind.len() > 1 {
+            rewind.step_back(&mut machine).unwrap();
+        }
+        rewind.step_back(&mut machine).unwrap();
+        assert_eq!(oldest, machine.cpu().v()[0].read_reg());
+    }
+}
@@ -0,0 +1,105 @@
+//where CXNN gets its random bytes from
+//the whole state fits in a u64 so it can be saved with the machine, and Send so a machine can move threads
+pub trait RandomSource: Send {
+    fn next_byte(&mut self) -> u8;
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+//xorshift64*, small and fast with a reproducible sequence for a given seed
+pub struct Xorshift {
+    state: u64
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Xorshift {
+        let mut rng = Xorshift { state: 0 };
+        rng.set_state(seed);
+        rng
+    }
+
+    //a different sequence every run, for when no seed is given
+    pub fn from_entropy() -> Xorshift {
+        Xorshift::new(rand::random())
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    //xorshift never leaves zero, so a zero seed is swapped for a fixed one
+    fn set_state(&mut self, state: u64) {
+        self.state = if state == 0 {0x9E37_79B9_7F4A_7C15} else {state};
+    }
+}
+
+//replays a fixed list of bytes in a loop, for tests that need known random values
+pub struct Scripted {
+    values: Vec<u8>,
+    position: usize
+}
+
+impl Scripted {
+    pub fn new(values: Vec<u8>) -> Scripted {
+        Scripted { values, position: 0 }
+    }
+}
+
+impl RandomSource for Scripted {
+    fn next_byte(&mut self) -> u8 {
+        if self.values.is_empty() {
+            return 0;
+        }
+        let value = self.values[self.position % self.values.len()];
+        self.position = (self.position + 1) % self.values.len();
+        value
+    }
+
+    fn state(&self) -> u64 {
+        self.position as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.position = state as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xorshift_test() {
+        let mut a = Xorshift::new(42);
+        let mut b = Xorshift::new(42);
+        let sequence: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        assert_eq!(sequence, (0..32).map(|_| b.next_byte()).collect::<Vec<u8>>());
+        assert!(sequence.iter().any(|&byte| byte != sequence[0]));
+        assert_ne!(sequence, (0..32).map(|_| Xorshift::new(43).next_byte()).collect::<Vec<u8>>());
+
+        let state = a.state();
+        let next = a.next_byte();
+        b.set_state(state);
+        assert_eq!(next, b.next_byte());
+        assert_ne!(0, Xorshift::new(0).next_byte() as u64 | Xorshift::new(0).state());
+    }
+
+    #[test]
+    fn scripted_test() {
+        let mut rng = Scripted::new(vec![1, 2, 3]);
+        assert_eq!(vec![1, 2, 3, 1], (0..4).map(|_| rng.next_byte()).collect::<Vec<u8>>());
+        rng.set_state(2);
+        assert_eq!(3, rng.next_byte());
+        assert_eq!(0, rng.state());
+        assert_eq!(0, Scripted::new(Vec::new()).next_byte());
+    }
+}
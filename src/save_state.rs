@@ -0,0 +1,179 @@
+use crate::error::EmulatorError;
+
+pub const MAGIC: &[u8; 4] = b"F8ST";
+//bump whenever a field is added, older versions are migrated on load
+pub const VERSION: u16 = 4;
+
+//builds a save state out of fixed width big endian fields, after the magic and version
+pub struct StateWriter {
+    bytes: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::with_header(MAGIC, VERSION)
+    }
+
+    //other file formats reuse the same encoding behind their own magic
+    pub fn with_header(magic: &[u8; 4], version: u16) -> StateWriter {
+        let mut writer = StateWriter { bytes: Vec::new() };
+        writer.write_bytes(magic);
+        writer.write_u16(version);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+//reads the fields back in the order they were written
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    version: u16
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<StateReader<'a>, EmulatorError> {
+        StateReader::with_header(bytes, MAGIC, VERSION)
+    }
+
+    //accepts versions 1 to version of the format with the given magic
+    pub fn with_header(bytes: &'a [u8], magic: &[u8; 4], version: u16) -> Result<StateReader<'a>, EmulatorError> {
+        if bytes.len() < magic.len() || &bytes[..magic.len()] != magic {
+            return Err(invalid(&format!("missing the {} header", String::from_utf8_lossy(magic))));
+        }
+        let mut reader = StateReader { bytes, position: magic.len(), version: 0 };
+        reader.version = reader.read_u16()?;
+        if reader.version == 0 || reader.version > version {
+            return Err(invalid(&format!("unsupported version {}, expected at most {}", reader.version, version)));
+        }
+        Ok(reader)
+    }
+
+    //the version the state was written with, for migrating older layouts
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], EmulatorError> {
+        let bytes = self.bytes.get(self.position..self.position + length).ok_or_else(|| invalid("truncated"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, EmulatorError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, EmulatorError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, EmulatorError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, EmulatorError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(invalid(&format!("expected a flag but found {}", value)))
+        }
+    }
+
+    //fails if anything is left over, which means the layout did not match
+    pub fn finish(&self) -> Result<(), EmulatorError> {
+        if self.position != self.bytes.len() {
+            return Err(invalid(&format!("{} unexpected trailing bytes", self.bytes.len() - self.position)));
+        }
+        Ok(())
+    }
+}
+
+pub fn invalid(reason: &str) -> EmulatorError {
+    EmulatorError::InvalidSaveState(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0xAB);
+        writer.write_u16(0x1234);
+        writer.write_u32(0xDEAD_BEEF);
+        writer.write_u64(0x0102_0304_0506_0708);
+        writer.write_bool(true);
+        writer.write_bytes(&[1, 2, 3]);
+        let bytes = writer.finish();
+        assert_eq!(b"F8ST", &bytes[..4]);
+
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(VERSION, reader.version());
+        assert_eq!(0xAB, reader.read_u8().unwrap());
+        assert_eq!(0x1234, reader.read_u16().unwrap());
+        assert_eq!(0xDEAD_BEEF, reader.read_u32().unwrap());
+        assert_eq!(0x0102_0304_0506_0708, reader.read_u64().unwrap());
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(&[1, 2, 3], reader.read_bytes(3).unwrap());
+        assert!(reader.finish().is_ok());
+        assert!(reader.read_u8().is_err());
+    }
+
+    #[test]
+    fn header_test() {
+        assert!(StateReader::new(b"F8S").is_err());
+        assert!(StateReader::new(b"XXST\x00\x01").is_err());
+        assert!(StateReader::new(b"F8ST\x00").is_err());
+        assert!(StateReader::new(b"F8ST\x00\x00").is_err());
+        assert!(StateReader::new(b"F8ST\xFF\xFF").is_err());
+        assert!(StateReader::with_header(b"F8MV\x00\x01", b"F8MV", 1).is_ok());
+        assert!(StateReader::with_header(b"F8ST\x00\x01", b"F8MV", 1).is_err());
+        let mut reader = StateReader::new(b"F8ST\x00\x01\x02").unwrap();
+        assert!(reader.finish().is_err());
+        assert!(reader.read_bool().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u32 = 60;
+
+//paces frames against a fixed timeline, so oversleeping one frame is made up in the next
+pub struct FrameScheduler {
+    //the frame length at normal speed
+    base: Duration,
+    frame: Duration,
+    next: Instant
+}
+
+impl FrameScheduler {
+    pub fn new(frame_rate: u32) -> FrameScheduler {
+        let frame = Duration::from_secs(1) / frame_rate;
+        FrameScheduler { base: frame, frame, next: Instant::now() + frame }
+    }
+
+    //stretches or shrinks frames, e.g. 0.5 for half speed
+    pub fn set_speed(&mut self, speed: f64) {
+        self.frame = self.base.div_f64(speed);
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame
+    }
+
+    //how long to wait at now for the next frame, moving the deadline one frame on
+    pub fn delay(&mut self, now: Instant) -> Duration {
+        let delay = self.next.saturating_duration_since(now);
+        if now > self.next + self.frame {
+            //more than a frame behind, e.g. after sitting at a breakpoint, so start afresh instead of rushing to catch up
+            self.next = now + self.frame;
+        } else {
+            self.next += self.frame;
+        }
+        delay
+    }
+
+    //sleeps until the next frame is due
+    pub fn wait(&mut self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_test() {
+        let mut scheduler = FrameScheduler::new(50);
+        let frame = scheduler.frame_duration();
+        assert_eq!(Duration::from_millis(20), frame);
+        let start = scheduler.next - frame;
+        //5ms of work leaves 15ms to sleep
+        assert_eq!(Duration::from_millis(15), scheduler.delay(start + Duration::from_millis(5)));
+        //oversleeping the first deadline by 4ms shortens the next wait by as much
+        assert_eq!(Duration::from_millis(12), scheduler.delay(start + Duration::from_millis(28)));
+        //running late by less than a frame skips the sleep but keeps the timeline
+        assert_eq!(Duration::ZERO, scheduler.delay(start + Duration::from_millis(70)));
+        assert_eq!(Duration::from_millis(10), scheduler.delay(start + Duration::from_millis(70)));
+    }
+
+    #[test]
+    fn speed_test() {
+        let mut scheduler = FrameScheduler::new(50);
+        scheduler.set_speed(0.25);
+        assert_eq!(Duration::from_millis(80), scheduler.frame_duration());
+        let start = scheduler.next - Duration::from_millis(20);
+        assert_eq!(Duration::from_millis(20), scheduler.delay(start));
+        assert_eq!(Duration::from_millis(80), scheduler.delay(start + Duration::from_millis(20)));
+        scheduler.set_speed(2.0);
+        assert_eq!(Duration::from_millis(10), scheduler.frame_duration());
+    }
+
+    #[test]
+    fn resync_test() {
+        let mut scheduler = FrameScheduler::new(50);
+        let start = scheduler.next - scheduler.frame_duration();
+        assert_eq!(Duration::ZERO, scheduler.delay(start + Duration::from_secs(5)));
+        assert_eq!(Duration::from_millis(20), scheduler.delay(start + Duration::from_secs(5)));
+    }
+}
@@ -1,7 +1,8 @@
 use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
+use crate::cpu::BUZZER_PATTERN;
 
 pub struct Speaker{
-    device: AudioDevice<SquareWave>
+    device: AudioDevice<PatternWave>
 }
 
 impl Speaker{
@@ -13,11 +14,7 @@ impl Speaker{
             samples: None
         };
         let dev = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            SquareWave{
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
+            PatternWave::new(spec.freq, 0.25)
         }).unwrap();
         Speaker{
             device: dev
@@ -30,27 +27,111 @@ impl Speaker{
     pub fn stop(&self){
         self.device.pause();
     }
+
+    pub fn set_pattern(&mut self, pattern: &[u8; 16]){
+        self.device.lock().pattern = *pattern;
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8){
+        self.device.lock().set_pitch(pitch);
+    }
+
+    //plays patterns faster or slower along with the emulation
+    pub fn set_speed(&mut self, speed: f32){
+        self.device.lock().set_speed(speed);
+    }
 }
 
+//XO-CHIP pitch register to pattern bits played per second
+fn playback_rate(pitch: u8) -> f32{
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
 
-//from rust docs
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
+//plays a looping 128 sample 1-bit pattern
+struct PatternWave {
+    pattern: [u8; 16],
+    freq: i32,
+    pitch: u8,
+    speed: f32,
+    //pattern bits advanced per output sample
+    step: f32,
+    position: f32,
     volume: f32
 }
 
-impl AudioCallback for SquareWave {
+impl PatternWave {
+    fn new(freq: i32, volume: f32) -> PatternWave {
+        let mut wave = PatternWave{
+            pattern: BUZZER_PATTERN,
+            freq,
+            pitch: 64,
+            speed: 1.0,
+            step: 0.0,
+            position: 0.0,
+            volume
+        };
+        wave.set_pitch(64);
+        wave
+    }
+
+    fn set_pitch(&mut self, pitch: u8){
+        self.pitch = pitch;
+        self.step = playback_rate(pitch) * self.speed / self.freq as f32;
+    }
+
+    fn set_speed(&mut self, speed: f32){
+        self.speed = speed;
+        self.set_pitch(self.pitch);
+    }
+}
+
+impl AudioCallback for PatternWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = match self.phase {
-                0.0...0.5 => self.volume,
-                _ => -self.volume
+            let bit = self.position as usize;
+            *x = if self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                self.volume
+            } else {
+                -self.volume
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            self.position = (self.position + self.step) % 128.0;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playback_rate_test() {
+        assert_eq!(4000.0, playback_rate(64));
+        assert_eq!(8000.0, playback_rate(112));
+        assert_eq!(2000.0, playback_rate(16));
+    }
+
+    #[test]
+    fn pattern_callback_test() {
+        let mut wave = PatternWave::new(4000, 0.5);
+        wave.pattern = [0; 16];
+        wave.pattern[0] = 0xA0;
+        let mut out = [0.0; 4];
+        wave.callback(&mut out);
+        assert_eq!([0.5, -0.5, 0.5, -0.5], out);
+    }
+
+    #[test]
+    fn speed_test() {
+        let mut wave = PatternWave::new(4000, 0.5);
+        wave.pattern = [0; 16];
+        wave.pattern[0] = 0xCC;
+        wave.set_speed(0.5);
+        let mut out = [0.0; 4];
+        wave.callback(&mut out);
+        assert_eq!([0.5, 0.5, 0.5, 0.5], out);
+        wave.set_pitch(64);
+        assert_eq!(0.5, wave.step);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::cpu::CPU;
+use crate::instruction::Instruction;
+
+//the VIP clocks its CDP1802 at 1.76 MHz, and a machine cycle takes 8 clocks
+pub const VIP_CYCLES_PER_SECOND: u32 = 1_760_640 / 8;
+pub const VIP_CYCLES_PER_FRAME: u32 = VIP_CYCLES_PER_SECOND / 60;
+//every instruction first pays for the interpreter's fetch and decode
+const FETCH: u32 = 40;
+
+//what an instruction did, for the costs that depend on it rather than on the registers it started with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Outcome {
+    //a skip instruction jumped over the next one
+    pub skipped: bool,
+    //FX1E carried into the high byte of I
+    pub page_crossed: bool
+}
+
+//VIP machine cycles spent on an instruction, given the registers before it runs, as counted in
+//Laurence Scotford's walkthrough of the VIP interpreter. Skips are priced as not taken and FX1E as
+//staying on its page, outcome_cycles adds the rest once the instruction has run
+//SUPER-CHIP and XO-CHIP instructions never ran on a VIP, so they cost the same as a register load
+pub fn vip_cycles(instruction: &Instruction, cpu: &CPU) -> u32 {
+    use Instruction::*;
+
+    FETCH + match *instruction {
+        Sys(_) => 0,
+        Cls => 3078,
+        Ret => 10,
+        Jp(_) => 12,
+        Call(_) => 26,
+        SeImm{..} | SneImm{..} => 10,
+        SeReg{..} | SneReg{..} => 14,
+        LdImm{..} => 6,
+        AddImm{..} => 10,
+        LdReg{..} | Or{..} | And{..} | Xor{..} | AddReg{..} | Sub{..} | Shr{..} | Subn{..} | Shl{..} => 44,
+        LdI(_) => 12,
+        JpV0(_) => 22,
+        Rnd{..} => 36,
+        Drw{x, n, ..} => draw_cycles(cpu.v()[x as usize].read_reg(), n),
+        Skp{..} | Sknp{..} => 14,
+        LdVxDt{..} | LdDtVx{..} | LdStVx{..} | LdVxK{..} => 10,
+        AddI{..} => 12,
+        LdF{..} => 20,
+        LdB{x} => bcd_cycles(cpu.v()[x as usize].read_reg()),
+        LdIVx{x} | LdVxI{x} => 14 + 14 * (x as u32 + 1),
+        _ => 6
+    }
+}
+
+//the extra cycles taken by a skip that jumps and by FX1E moving I onto another page
+pub fn outcome_cycles(instruction: &Instruction, outcome: Outcome) -> u32 {
+    use Instruction::*;
+
+    match *instruction {
+        SeImm{..} | SneImm{..} | SeReg{..} | SneReg{..} | Skp{..} | Sknp{..} if outcome.skipped => 4,
+        AddI{..} if outcome.page_crossed => 4,
+        _ => 0
+    }
+}
+
+//each decimal digit is found by subtracting its power of ten until it goes negative
+fn bcd_cycles(vx: u8) -> u32 {
+    let digits = (vx / 100 + vx / 10 % 10 + vx % 10) as u32;
+    80 + 16 * digits
+}
+
+//sprites are drawn a byte per row, and rows that straddle two screen bytes have to be
+//shifted into place a bit at a time, so unaligned sprites cost more the further they are shifted
+fn draw_cycles(vx: u8, n: u8) -> u32 {
+    let rows = if n == 0 {16} else {n as u32};
+    let shift = (vx % 8) as u32;
+    let row = if shift == 0 {22} else {34 + 6 * shift};
+    26 + rows * row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use crate::ram::RAM;
+
+    #[test]
+    fn vip_cycles_test() {
+        let mut cpu = CPU::new(RAM::new(), Quirks::vip());
+        assert_eq!(3668, VIP_CYCLES_PER_FRAME);
+        assert_eq!(46, vip_cycles(&Instruction::LdImm{x: 0, kk: 1}, &cpu));
+        assert!(vip_cycles(&Instruction::Cls, &cpu) > 50 * vip_cycles(&Instruction::LdImm{x: 0, kk: 1}, &cpu));
+        assert_eq!(40 + 14 + 14 * 4, vip_cycles(&Instruction::LdIVx{x: 3}, &cpu));
+
+        let aligned = vip_cycles(&Instruction::Drw{x: 1, y: 2, n: 5}, &cpu);
+        assert_eq!(40 + 26 + 5 * 22, aligned);
+        assert!(vip_cycles(&Instruction::Drw{x: 1, y: 2, n: 10}, &cpu) > aligned);
+        cpu.v_mut()[1].write_reg(3);
+        let shifted = vip_cycles(&Instruction::Drw{x: 1, y: 2, n: 5}, &cpu);
+        assert!(shifted > aligned);
+        cpu.v_mut()[1].write_reg(7);
+        assert!(vip_cycles(&Instruction::Drw{x: 1, y: 2, n: 5}, &cpu) > shifted);
+        cpu.v_mut()[1].write_reg(8);
+        assert_eq!(aligned, vip_cycles(&Instruction::Drw{x: 1, y: 2, n: 5}, &cpu));
+    }
+
+    #[test]
+    fn outcome_cycles_test() {
+        let skipped = Outcome { skipped: true, page_crossed: false };
+        let crossed = Outcome { skipped: false, page_crossed: true };
+        assert_eq!(0, outcome_cycles(&Instruction::SeImm{x: 0, kk: 1}, Outcome::default()));
+        assert_eq!(4, outcome_cycles(&Instruction::SeImm{x: 0, kk: 1}, skipped));
+        assert_eq!(4, outcome_cycles(&Instruction::Sknp{x: 0}, skipped));
+        assert_eq!(0, outcome_cycles(&Instruction::AddI{x: 0}, skipped));
+        assert_eq!(4, outcome_cycles(&Instruction::AddI{x: 0}, crossed));
+        assert_eq!(0, outcome_cycles(&Instruction::Jp(0x200), skipped));
+    }
+
+    #[test]
+    fn bcd_cycles_test() {
+        let mut cpu = CPU::new(RAM::new(), Quirks::vip());
+        assert_eq!(40 + 80, vip_cycles(&Instruction::LdB{x: 2}, &cpu));
+        cpu.v_mut()[2].write_reg(255);
+        assert_eq!(40 + 80 + 16 * 12, vip_cycles(&Instruction::LdB{x: 2}, &cpu));
+        cpu.v_mut()[2].write_reg(100);
+        assert_eq!(40 + 80 + 16, vip_cycles(&Instruction::LdB{x: 2}, &cpu));
+    }
+}
@@ -5,8 +5,11 @@ pub struct WordRegister {
 
 impl WordRegister {
     pub fn new(name: String) -> WordRegister {
-        let mut reg : WordRegister = WordRegister { value: 0, name};
-        reg
+        WordRegister { value: 0, name}
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
     pub fn read_reg(&self) -> u16 {
@@ -18,19 +21,19 @@ impl WordRegister {
     }
 
     pub fn increment_reg(&mut self) {
-        self.value += 1;
+        self.value = self.value.wrapping_add(1);
     }
 
     pub fn decrement_reg(&mut self) {
-        self.value -= 1;
+        self.value = self.value.wrapping_sub(1);
     }
 
     pub fn next_instruction(&mut self) {
-        self.value += 2;
+        self.value = self.value.wrapping_add(2);
     }
 
     pub fn wait_instruction(&mut self) {
-        self.value -= 2;
+        self.value = self.value.wrapping_sub(2);
     }
 }
 
@@ -77,4 +80,17 @@ mod tests {
         wr.wait_instruction();
         assert_eq!(0x4FFF, wr.read_reg());
     }
+
+    #[test]
+    fn wrap_test(){
+        let mut wr = WordRegister::new(String::new());
+        wr.wait_instruction();
+        assert_eq!(0xFFFE, wr.read_reg());
+        wr.next_instruction();
+        wr.increment_reg();
+        assert_eq!(0x0001, wr.read_reg());
+        wr.decrement_reg();
+        wr.decrement_reg();
+        assert_eq!(0xFFFF, wr.read_reg());
+    }
 }